@@ -2,5 +2,11 @@
 
 execho() { echo "\$ $@" ; "$@" ; }
 
-mode=`echo -e 'staging\ndynamic' | sort -R | tail -n 1`
-cargo run --release --bin $mode -- 64 256
+# the strategies given as arguments, all of them otherwise, one run each
+strategies="$@"
+if [ -z "$strategies" ]; then
+    strategies=`cargo run --release --bin primus_polygoni -- --list | cut -d : -f 1`
+fi
+for strategy in $strategies; do
+    execho cargo run --release --bin primus_polygoni -- 64 256 --strategy $strategy
+done
@@ -0,0 +1,133 @@
+/// Layer of one of the scene texture arrays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Slot {
+    pub array: usize,
+    pub layer: usize,
+}
+
+/// Hands out texture array layers, opening a new array whenever
+/// the previous ones are full, since drivers limit the number of layers
+/// of a single array (`GL_MAX_ARRAY_TEXTURE_LAYERS`, often 256 or 2048).
+#[derive(Debug, Clone)]
+pub struct LayerAllocator {
+    layers_per_array: usize,
+    /// Layers ever handed out in each array, all but the last one are full.
+    used: Vec<usize>,
+    /// Layers given back, reused before opening new ones.
+    free: Vec<Slot>,
+}
+
+impl LayerAllocator {
+    pub fn new(layers_per_array: usize) -> Self {
+        assert!(layers_per_array > 0, "texture arrays need at least one layer");
+        LayerAllocator {
+            layers_per_array: layers_per_array,
+            used: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn layers_per_array(&self) -> usize {
+        self.layers_per_array
+    }
+
+    pub fn allocate(&mut self) -> Slot {
+        if let Some(slot) = self.free.pop() {
+            return slot;
+        }
+
+        let full = self.used.last().map(|&used| used == self.layers_per_array).unwrap_or(true);
+        if full {
+            self.used.push(0);
+        }
+        let array = self.used.len() - 1;
+        let layer = self.used[array];
+        self.used[array] += 1;
+        Slot { array: array, layer: layer }
+    }
+
+    pub fn free(&mut self, slot: Slot) {
+        debug_assert!(slot.array < self.used.len() && slot.layer < self.used[slot.array],
+                      "{:?} was never allocated", slot);
+        debug_assert!(!self.free.contains(&slot), "{:?} is freed twice", slot);
+        self.free.push(slot);
+    }
+
+    pub fn array_count(&self) -> usize {
+        self.used.len()
+    }
+
+    /// Layers `array` must have to hold every slot handed out in it.
+    pub fn array_layers(&self, array: usize) -> usize {
+        self.used[array]
+    }
+
+    /// Slots currently handed out.
+    pub fn allocated(&self) -> usize {
+        self.used.iter().sum::<usize>() - self.free.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_arrays_in_order() {
+        let mut layers = LayerAllocator::new(3);
+        let slots: Vec<_> = (0..7).map(|_| layers.allocate()).collect();
+        let expected: Vec<_> = (0..7).map(|i| Slot { array: i / 3, layer: i % 3 }).collect();
+        assert_eq!(slots, expected);
+
+        assert_eq!(layers.array_count(), 3);
+        assert_eq!(layers.array_layers(0), 3);
+        assert_eq!(layers.array_layers(1), 3);
+        assert_eq!(layers.array_layers(2), 1);
+        assert_eq!(layers.allocated(), 7);
+    }
+
+    #[test]
+    fn never_exceeds_the_limit() {
+        for &limit in &[1, 2, 256, 2048] {
+            let mut layers = LayerAllocator::new(limit);
+            for _ in 0..3000 {
+                let slot = layers.allocate();
+                assert!(slot.layer < limit);
+            }
+            assert_eq!(layers.layers_per_array(), limit);
+            assert_eq!(layers.array_count(), (3000 + limit - 1) / limit);
+            for a in 0..layers.array_count() {
+                assert!(layers.array_layers(a) <= limit);
+            }
+        }
+    }
+
+    #[test]
+    fn reuses_freed_layers() {
+        let mut layers = LayerAllocator::new(2);
+        let a = layers.allocate();
+        let b = layers.allocate();
+        let c = layers.allocate();
+        assert_eq!(layers.array_count(), 2);
+
+        layers.free(b);
+        layers.free(a);
+        assert_eq!(layers.allocated(), 1);
+        // the last freed first
+        assert_eq!(layers.allocate(), a);
+        assert_eq!(layers.allocate(), b);
+        assert_eq!(layers.allocated(), 3);
+
+        // no more free layers, the second array gets its second layer
+        assert_eq!(layers.allocate(), Slot { array: 1, layer: 1 });
+        assert_eq!(layers.array_count(), 2);
+        assert_eq!(layers.array_layers(1), 2);
+        assert!(c != a && c != b);
+    }
+
+    #[test]
+    #[should_panic]
+    fn needs_a_layer() {
+        LayerAllocator::new(0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::f32::consts::PI;
+use rand::{Rng, XorShiftRng};
+use nalgebra::{self, Vector3, UnitQuaternion};
+use scene::Sphere;
+
+/// Circular orbit around another sphere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Orbit {
+    /// Index of the sphere orbited, which is never a moon itself.
+    pub parent: usize,
+    /// Distance between the centers.
+    pub radius: f32,
+    /// Angular speed, in rad/s.
+    pub rate: f32,
+    /// Rotates the xz plane into the orbital plane.
+    pub plane: UnitQuaternion<f32>,
+    pub phase: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motion {
+    /// Rotation applied each second around the sphere center.
+    pub spin: Vector3<f32>,
+    pub orbit: Option<Orbit>,
+}
+
+/// How far moons can be from the sphere they orbit,
+/// beyond the sphere radius.
+pub const MOON_MARGIN: f32 = 2.5;
+
+/// Spins every sphere and, with moons, turns about one in four into
+/// the moon of the sphere before it, shrinking it on the way.
+pub struct Animation {
+    pub motions: Vec<Motion>,
+    pub rotations: Vec<UnitQuaternion<f32>>,
+    time: f32,
+}
+
+impl Animation {
+    pub fn new(spheres: &mut [Sphere], moons: bool, rng: &mut XorShiftRng) -> Self {
+        let mut motions: Vec<Motion> = Vec::with_capacity(spheres.len());
+
+        for i in 0..spheres.len() {
+            let axis = Vector3::new(rng.gen_range(-0.3, 0.3), 1., rng.gen_range(-0.3, 0.3));
+            let spin = axis / axis.norm() * rng.gen_range(0.1, 0.6);
+
+            let parent_is_planet = moons && i > 0 && motions[i - 1].orbit.is_none();
+            let orbit = if parent_is_planet && rng.gen_weighted_bool(4) {
+                let parent = spheres[i - 1];
+                spheres[i].radius = parent.radius * rng.gen_range(0.2, 0.4);
+                let tilt = Vector3::new(rng.gen_range(-0.4, 0.4), 0., rng.gen_range(-0.4, 0.4));
+                Some(Orbit {
+                    parent: i - 1,
+                    radius: parent.radius + spheres[i].radius +
+                        rng.gen_range(0.3, MOON_MARGIN - 2. * spheres[i].radius),
+                    rate: rng.gen_range(0.3, 1.2) * if rng.gen() { 1. } else { -1. },
+                    plane: UnitQuaternion::new(tilt),
+                    phase: rng.gen_range(0., 2. * PI),
+                })
+            } else {
+                None
+            };
+
+            motions.push(Motion { spin: spin, orbit: orbit });
+        }
+
+        let mut animation = Animation {
+            rotations: vec![nalgebra::one(); spheres.len()],
+            motions: motions,
+            time: 0.,
+        };
+        animation.place_moons(spheres);
+        animation
+    }
+
+    pub fn update(&mut self, spheres: &mut [Sphere], delta: f32) {
+        self.time += delta;
+        for (rotation, motion) in self.rotations.iter_mut().zip(&self.motions) {
+            *rotation = UnitQuaternion::new(motion.spin * delta) * *rotation;
+        }
+        self.place_moons(spheres);
+    }
+
+    fn place_moons(&self, spheres: &mut [Sphere]) {
+        for (i, motion) in self.motions.iter().enumerate() {
+            if let Some(ref orbit) = motion.orbit {
+                let angle = orbit.phase + orbit.rate * self.time;
+                let offset = Vector3::new(angle.cos(), 0., angle.sin()) * orbit.radius;
+                spheres[i].center = spheres[orbit.parent].center + orbit.plane * offset;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{self, Point3};
+    use rand::{SeedableRng, XorShiftRng};
+    use scene::Sphere;
+    use super::*;
+
+    fn spheres() -> Vec<Sphere> {
+        (0..100).map(|i| Sphere {
+            center: Point3::new(i as f32 * 4., 0., 0.),
+            radius: 1.,
+        }).collect()
+    }
+
+    #[test]
+    fn placement_kept_without_moons() {
+        let mut moved = spheres();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut animation = Animation::new(&mut moved, false, &mut rng);
+        assert!(animation.motions.iter().all(|motion| motion.orbit.is_none()));
+
+        animation.update(&mut moved, 1.);
+        assert_eq!(moved, spheres());
+    }
+
+    #[test]
+    fn moons_orbit_planets() {
+        let mut moved = spheres();
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut animation = Animation::new(&mut moved, true, &mut rng);
+        let moons: Vec<_> = animation.motions.iter().enumerate()
+            .filter_map(|(i, motion)| motion.orbit.map(|orbit| (i, orbit)))
+            .collect();
+        assert!(!moons.is_empty());
+
+        for _ in 0..10 {
+            animation.update(&mut moved, 0.5);
+            for &(i, orbit) in &moons {
+                assert!(animation.motions[orbit.parent].orbit.is_none());
+                let parent = moved[orbit.parent];
+                let distance = nalgebra::distance(&moved[i].center, &parent.center);
+                assert!((distance - orbit.radius).abs() < 1e-3);
+                assert!(distance + moved[i].radius <= parent.radius + MOON_MARGIN + 1e-3);
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use rand::{Rng, XorShiftRng};
+
+/// Shell drawn around a sphere, brightest on its rim.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    pub color: [f32; 3],
+    /// Height of the shell, relative to the sphere radius.
+    /// 0 means no atmosphere.
+    pub thickness: f32,
+}
+
+impl Atmosphere {
+    pub fn none() -> Self {
+        Atmosphere { color: [0., 0., 0.], thickness: 0. }
+    }
+
+    /// Mostly bluish, sometimes reddish or greenish.
+    pub fn random(rng: &mut XorShiftRng) -> Self {
+        let tint = rng.gen_range(0., 0.4);
+        let color = match rng.gen_range(0, 6) {
+            0 => [0.8, 0.4 + tint, 0.2],
+            1 => [0.3, 0.8, 0.3 + tint],
+            _ => [0.3 + tint, 0.5 + tint, 1.0],
+        };
+        Atmosphere {
+            color: color,
+            thickness: rng.gen_range(0.05, 0.15),
+        }
+    }
+
+    /// Color, with the thickness in `w`.
+    pub fn gpu(&self) -> [f32; 4] {
+        let c = self.color;
+        [c[0], c[1], c[2], self.thickness]
+    }
+}
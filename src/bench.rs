@@ -0,0 +1,548 @@
+//! Headless benchmark, driven by a script instead of input.
+
+use std::{fs, io};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::f32::consts::PI;
+use time::precise_time_s;
+use {gfx, gfx_device_gl, glutin, layout};
+use gfx::Factory;
+use scene::{Scene, Impl, ColorFormat, DepthFormat};
+use strategy::{self, Strategy};
+
+/// Size of the offscreen targets.
+const WIDTH: u16 = 1280;
+const HEIGHT: u16 = 720;
+
+/// Simulated time between frames, so that every run sees the same frames.
+const STEP: f32 = 1. / 60.;
+
+/// What to run, read from `key value` lines, `#` starting comments:
+///
+/// ```text
+/// strategy staging
+/// sphere_count 64
+/// texture_size 256
+/// layout ring
+/// sky false
+/// animate true
+/// async false
+/// warmup 60
+/// regenerate 30
+/// duration 10
+/// output staging.csv
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    /// One of `strategy::NAMES`.
+    pub strategy: String,
+    pub sphere_count: usize,
+    pub texture_size: usize,
+    /// One of `layout::NAMES`.
+    pub layout: String,
+    /// Whether to draw the skybox, which also takes a texture layer.
+    pub sky: bool,
+    /// Whether planets spin and moons orbit, streaming the instances every frame.
+    pub animate: bool,
+    /// Whether to go on without waiting for the uploads to finish.
+    pub async: bool,
+    /// Frames run before measuring.
+    pub warmup: usize,
+    /// New textures are generated every this many frames, only once if 0.
+    pub regenerate: usize,
+    /// Seconds measured after the warmup.
+    pub duration: f64,
+    /// Where to write the samples, as JSON if it ends with `.json`, CSV otherwise.
+    pub output: PathBuf,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script {
+            strategy: String::from("staging"),
+            sphere_count: 64,
+            texture_size: 128,
+            layout: String::from("ring"),
+            sky: false,
+            animate: true,
+            async: false,
+            warmup: 60,
+            regenerate: 30,
+            duration: 10.,
+            output: PathBuf::from("bench.csv"),
+        }
+    }
+}
+
+fn invalid<E>(error: E) -> io::Error
+    where E: Into<Box<::std::error::Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+impl Script {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut text = String::new();
+        try!(try!(fs::File::open(path)).read_to_string(&mut text));
+        Script::parse(&text)
+    }
+
+    /// Reads a script, unspecified keys keep their default value.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut script = Script::default();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut split = line.splitn(2, char::is_whitespace);
+            let key = split.next().unwrap();
+            let value = try!(split.next()
+                .map(|v| v.trim())
+                .ok_or_else(|| invalid(format!("expected a value after {}", key))));
+            match key {
+                "strategy" => script.strategy = value.to_string(),
+                "sphere_count" => script.sphere_count = try!(value.parse().map_err(invalid)),
+                "texture_size" => script.texture_size = try!(value.parse().map_err(invalid)),
+                "layout" => script.layout = value.to_string(),
+                "sky" => script.sky = try!(value.parse().map_err(invalid)),
+                "animate" => script.animate = try!(value.parse().map_err(invalid)),
+                "async" => script.async = try!(value.parse().map_err(invalid)),
+                "warmup" => script.warmup = try!(value.parse().map_err(invalid)),
+                "regenerate" => script.regenerate = try!(value.parse().map_err(invalid)),
+                "duration" => script.duration = try!(value.parse().map_err(invalid)),
+                "output" => script.output = PathBuf::from(value),
+                _ => return Err(invalid(format!("unknown key: {}", key))),
+            }
+        }
+
+        if !strategy::NAMES.contains(&&script.strategy[..]) {
+            return Err(invalid(format!("unknown strategy {}, expected one of {:?}",
+                                       script.strategy, strategy::NAMES)));
+        }
+        if !layout::NAMES.contains(&&script.layout[..]) {
+            return Err(invalid(format!("unknown layout {}, expected one of {:?}",
+                                       script.layout, layout::NAMES)));
+        }
+        Ok(script)
+    }
+
+    /// Whether new textures are generated on `frame`, counted from 0.
+    pub fn regenerates(&self, frame: usize) -> bool {
+        frame == 0 || (self.regenerate > 0 && frame % self.regenerate == 0)
+    }
+}
+
+/// Timings of a measured frame, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Counted from the first frame, warmup included.
+    pub frame: usize,
+    /// Layers generated during the frame, 0 if none.
+    pub layers: usize,
+    /// Generating the textures and recording their upload.
+    pub generation: f64,
+    /// Submitting the upload, and waiting for it unless async.
+    /// When async, only the submission: the strategies that reuse their
+    /// buffers wait for it later, in the `fence_wait` of another frame.
+    pub upload: f64,
+    /// Part of `generation` spent waiting on fences.
+    pub fence_wait: f64,
+    /// The whole frame, rendering included.
+    pub frame_time: f64,
+}
+
+/// Runs the frames of `script` on `scene`, returning the measured ones.
+/// Measures `frames` frames if given, instead of `script.duration` seconds.
+///
+/// Textures are also updated when the texture pool reassigns layers,
+/// the camera turns around the scene at a fixed speed.
+pub fn run_frames<R, C, F, D, I>(script: &Script,
+                                 scene: &mut Scene<R, I>,
+                                 encoder: &mut gfx::Encoder<R, C>,
+                                 update_encoder: &mut gfx::Encoder<R, C>,
+                                 factory: &mut F,
+                                 device: &mut D,
+                                 frames: Option<usize>) -> Vec<Sample>
+    where R: gfx::Resources, C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+          D: gfx::Device<Resources=R, CommandBuffer=C>, I: Impl<R>
+{
+    let (width, height, _, _) = scene.color_target.get_dimensions();
+    let aspect_ratio = width as f32 / height as f32;
+
+    scene.animating = script.animate;
+
+    let mut samples = Vec::new();
+    let mut measure_start = None;
+    let mut frame = 0;
+    loop {
+        let start = precise_time_s();
+        if frame == script.warmup {
+            measure_start = Some(start);
+        }
+        if let Some(measure_start) = measure_start {
+            let done = match frames {
+                Some(frames) => samples.len() >= frames,
+                None => start - measure_start >= script.duration,
+            };
+            if done {
+                break;
+            }
+        }
+
+        scene.animate(STEP);
+        scene.camera.move_left(0.5 * PI * STEP);
+
+        let mut layers = 0;
+        let (mut generation, mut upload, mut fence_wait) = (0., 0., 0.);
+        if script.regenerates(frame) || scene.textures_stale() {
+            let before = precise_time_s();
+            layers = if script.regenerates(frame) {
+                scene.generate_textures(update_encoder, factory, device)
+            } else {
+                scene.update_textures(update_encoder, factory, device)
+            };
+            let generated = precise_time_s();
+            generation = (generated - before) * 1_000.;
+            fence_wait = scene.implementation.fence_wait() * 1_000.;
+
+            let fence = update_encoder.fenced_flush_no_reset(device, None).unwrap();
+            if !script.async {
+                device.wait_fence(&fence);
+            }
+            update_encoder.reset();
+            scene.implementation.submitted(fence);
+            upload = (precise_time_s() - generated) * 1_000.;
+        }
+
+        encoder.clear(&scene.color_target, [0.0, 0.0, 0.0, 1.0]);
+        encoder.clear_depth(&scene.depth_target, scene.camera.projection().depth_clear());
+        scene.render(aspect_ratio, encoder);
+        // without a swap chain to pace the frames, wait for them to be drawn
+        let fence = encoder.fenced_flush_no_reset(device, None).unwrap();
+        device.wait_fence(&fence);
+        encoder.reset();
+        device.cleanup();
+
+        if measure_start.is_some() {
+            samples.push(Sample {
+                frame: frame,
+                layers: layers,
+                generation: generation,
+                upload: upload,
+                fence_wait: fence_wait,
+                frame_time: (precise_time_s() - start) * 1_000.,
+            });
+        }
+        frame += 1;
+    }
+
+    samples
+}
+
+pub fn write_csv<W: Write>(w: &mut W, samples: &[Sample]) -> io::Result<()> {
+    try!(writeln!(w, "frame, layers, generation (ms), upload (ms), fence wait (ms), frame (ms)"));
+    for s in samples {
+        try!(writeln!(w, "{}, {}, {}, {}, {}, {}",
+                      s.frame, s.layers, s.generation, s.upload, s.fence_wait, s.frame_time));
+    }
+    Ok(())
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+pub fn write_json<W: Write>(w: &mut W, script: &Script, samples: &[Sample]) -> io::Result<()> {
+    try!(writeln!(w, "{{"));
+    try!(writeln!(w, "  \"strategy\": {},", json_string(&script.strategy)));
+    try!(writeln!(w, "  \"sphere_count\": {},", script.sphere_count));
+    try!(writeln!(w, "  \"texture_size\": {},", script.texture_size));
+    try!(writeln!(w, "  \"layout\": {},", json_string(&script.layout)));
+    try!(writeln!(w, "  \"sky\": {},", script.sky));
+    try!(writeln!(w, "  \"animate\": {},", script.animate));
+    try!(writeln!(w, "  \"async\": {},", script.async));
+    try!(writeln!(w, "  \"warmup\": {},", script.warmup));
+    try!(writeln!(w, "  \"regenerate\": {},", script.regenerate));
+    try!(writeln!(w, "  \"duration\": {},", script.duration));
+    try!(writeln!(w, "  \"frames\": ["));
+    for (i, s) in samples.iter().enumerate() {
+        try!(writeln!(w, "    {{ \"frame\": {}, \"layers\": {}, \"generation\": {}, \
+                          \"upload\": {}, \"fence_wait\": {}, \"frame_time\": {} }}{}",
+                      s.frame, s.layers, s.generation, s.upload, s.fence_wait, s.frame_time,
+                      if i + 1 < samples.len() { "," } else { "" }));
+    }
+    try!(writeln!(w, "  ]"));
+    writeln!(w, "}}")
+}
+
+/// Writes `samples` to `script.output`, in the format its extension asks for.
+pub fn save(script: &Script, samples: &[Sample]) -> io::Result<()> {
+    let mut w = io::BufWriter::new(try!(fs::File::create(&script.output)));
+    if script.output.extension().map(|e| e == "json").unwrap_or(false) {
+        write_json(&mut w, script, samples)
+    } else {
+        write_csv(&mut w, samples)
+    }
+}
+
+/// Runs the benchmark described by the script at `path`
+/// in a headless OpenGL context.
+pub fn run(path: &Path) {
+    let script = Script::load(path)
+        .unwrap_or_else(|e| panic!("could not load {}: {}", path.display(), e));
+    let strategy = Strategy::from_name(&script.strategy).unwrap();
+    let layout = layout::from_name(&script.layout).unwrap();
+    println!("benchmarking {}: {}", script.strategy, strategy.description());
+
+    let context = glutin::HeadlessRendererBuilder::new(WIDTH as u32, HEIGHT as u32)
+        .with_gl(glutin::GlRequest::GlThenGles {
+            opengl_version: (3, 2),
+            opengles_version: (2, 0)
+        })
+        .build()
+        .expect("could not create headless context");
+    unsafe { context.make_current().expect("could not make the context current") };
+    let (mut device, mut factory) = gfx_device_gl::create(|s| {
+        context.get_proc_address(s) as *const _
+    });
+
+    let (_, _, color_target) = factory.create_render_target::<ColorFormat>(WIDTH, HEIGHT)
+        .expect("could not create color target");
+    let (_, _, depth_target) = factory.create_depth_stencil::<DepthFormat>(WIDTH, HEIGHT)
+        .expect("could not create depth target");
+
+    let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+    let mut update_encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+    let max_layers = ::max_array_layers(&mut device);
+    let mut scene = Scene::new(strategy,
+                               script.sphere_count,
+                               script.texture_size,
+                               script.sphere_count,
+                               max_layers,
+                               &*layout,
+                               script.sky,
+                               color_target,
+                               depth_target,
+                               &mut factory,
+                               &mut encoder);
+
+    let samples = run_frames(&script, &mut scene, &mut encoder, &mut update_encoder,
+                             &mut factory, &mut device, None);
+    save(&script, &samples)
+        .unwrap_or_else(|e| panic!("could not write {}: {}", script.output.display(), e));
+    println!("{} frames written to {}", samples.len(), script.output.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::PathBuf;
+    use gfx_core::memory::Typed;
+    use {gfx, layout, null};
+    use scene::{Scene, ColorFormat, DepthFormat};
+    use strategy::Strategy;
+    use super::*;
+
+    fn sample(frame: usize, layers: usize) -> Sample {
+        Sample {
+            frame: frame,
+            layers: layers,
+            generation: 1.5,
+            upload: 0.25,
+            fence_wait: 0.,
+            frame_time: 2.,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(Script::parse("").unwrap(), Script::default());
+        assert_eq!(Script::parse("# nothing\n\n   \n").unwrap(), Script::default());
+    }
+
+    #[test]
+    fn parse() {
+        let script = Script::parse("strategy ring\nsphere_count 16\n").unwrap();
+        assert_eq!(script, Script {
+            strategy: String::from("ring"),
+            sphere_count: 16,
+            .. Script::default()
+        });
+
+        let script = Script::parse("  # every key\n\
+                                    strategy null\n\
+                                    sphere_count 8\n\
+                                    texture_size 32\n\
+                                    layout poisson\n\
+                                    sky true\n\
+                                    animate false\n\
+                                    async true\n\
+                                    warmup 0\n\
+                                    regenerate 0\n\
+                                    duration 0.5\n\
+                                    output out dir/null.json\n").unwrap();
+        assert_eq!(script, Script {
+            strategy: String::from("null"),
+            sphere_count: 8,
+            texture_size: 32,
+            layout: String::from("poisson"),
+            sky: true,
+            animate: false,
+            async: true,
+            warmup: 0,
+            regenerate: 0,
+            duration: 0.5,
+            output: PathBuf::from("out dir/null.json"),
+        });
+    }
+
+    #[test]
+    fn parse_errors() {
+        let invalid = |text: &str| {
+            Script::parse(text).err().map(|e| e.kind()) == Some(io::ErrorKind::InvalidData)
+        };
+        assert!(invalid("frames 10"));
+        assert!(invalid("warmup"));
+        assert!(invalid("warmup   "));
+        assert!(invalid("warmup ten"));
+        assert!(invalid("sphere_count -1"));
+        assert!(invalid("sky yes"));
+        assert!(invalid("strategy fastest"));
+        assert!(invalid("layout cube"));
+        assert!(invalid("strategy Staging"));
+    }
+
+    #[test]
+    fn regenerates() {
+        let script = Script { regenerate: 30, .. Script::default() };
+        let frames: Vec<_> = (0..100).filter(|&f| script.regenerates(f)).collect();
+        assert_eq!(frames, vec![0, 30, 60, 90]);
+
+        let once = Script { regenerate: 0, .. Script::default() };
+        let frames: Vec<_> = (0..100).filter(|&f| once.regenerates(f)).collect();
+        assert_eq!(frames, vec![0]);
+
+        let always = Script { regenerate: 1, .. Script::default() };
+        assert!((0..100).all(|f| always.regenerates(f)));
+    }
+
+    #[test]
+    fn csv() {
+        let mut output = Vec::new();
+        write_csv(&mut output, &[sample(60, 65), sample(61, 0)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "frame, layers, generation (ms), upload (ms), fence wait (ms), frame (ms)\n\
+                    60, 65, 1.5, 0.25, 0, 2\n\
+                    61, 0, 1.5, 0.25, 0, 2\n");
+
+        let mut output = Vec::new();
+        write_csv(&mut output, &[]).unwrap();
+        assert_eq!(output.iter().filter(|&&c| c == b'\n').count(), 1);
+    }
+
+    #[test]
+    fn json() {
+        let script = Script { output: PathBuf::from("bench.json"), .. Script::default() };
+        let mut output = Vec::new();
+        write_json(&mut output, &script, &[sample(60, 65), sample(61, 0)]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+{
+  \"strategy\": \"staging\",
+  \"sphere_count\": 64,
+  \"texture_size\": 128,
+  \"layout\": \"ring\",
+  \"sky\": false,
+  \"animate\": true,
+  \"async\": false,
+  \"warmup\": 60,
+  \"regenerate\": 30,
+  \"duration\": 10,
+  \"frames\": [
+    { \"frame\": 60, \"layers\": 65, \"generation\": 1.5, \"upload\": 0.25, \"fence_wait\": 0, \"frame_time\": 2 },
+    { \"frame\": 61, \"layers\": 0, \"generation\": 1.5, \"upload\": 0.25, \"fence_wait\": 0, \"frame_time\": 2 }
+  ]
+}
+");
+
+        let mut output = Vec::new();
+        write_json(&mut output, &script, &[]).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with("  \"frames\": [\n  ]\n}\n"));
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("ring"), "\"ring\"");
+        assert_eq!(json_string("a \"b\" \\c\n\u{1}é"), "\"a \\\"b\\\" \\\\c\\n\\u0001é\"");
+    }
+
+    #[test]
+    fn null_frames() {
+        let script = Script {
+            strategy: String::from("null"),
+            sphere_count: 4,
+            texture_size: 16,
+            warmup: 3,
+            regenerate: 2,
+            .. Script::default()
+        };
+
+        let (mut device, mut factory) = null::create();
+        let (_, _, color_target) = factory.create_render_target::<ColorFormat>(64, 32).unwrap();
+        let (_, _, depth_target) = factory.create_depth_stencil::<DepthFormat>(64, 32).unwrap();
+        let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+        let mut update_encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+        let layout = layout::from_name(&script.layout).unwrap();
+        let mut scene = Scene::new(Strategy::from_name(&script.strategy).unwrap(),
+                                   script.sphere_count,
+                                   script.texture_size,
+                                   script.sphere_count,
+                                   16,
+                                   &*layout,
+                                   script.sky,
+                                   color_target,
+                                   depth_target,
+                                   &mut factory,
+                                   &mut encoder);
+
+        let samples = run_frames(&script, &mut scene, &mut encoder, &mut update_encoder,
+                                 &mut factory, &mut device, Some(5));
+        assert_eq!(samples.len(), 5);
+        for (i, s) in samples.iter().enumerate() {
+            assert_eq!(s.frame, script.warmup + i);
+            // every sphere has a slot: layers are only generated when asked to,
+            // all of them along with the fallback
+            if script.regenerates(s.frame) {
+                assert_eq!(s.layers, script.sphere_count + 1);
+            } else {
+                assert_eq!(s.layers, 0);
+                assert_eq!(s.generation, 0.);
+            }
+            assert!(s.frame_time >= s.generation + s.upload);
+        }
+
+        // nothing uploaded to the textures, and every frame drawn
+        assert!(scene.arrays.iter().all(|array| {
+            device.texture_upload_size(*array.texture.raw().resource()) == 0
+        }));
+        let frames = script.warmup + samples.len();
+        assert!(device.draws.len() >= frames);
+    }
+}
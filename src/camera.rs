@@ -1,5 +1,36 @@
-use nalgebra::{self, Point3, Vector3, Isometry3, Perspective3, Matrix4,
-               UnitQuaternion};
+use nalgebra::{self, Point3, Vector2, Vector3, Isometry3, Perspective3, Orthographic3,
+               Matrix4, UnitQuaternion};
+use frustum::Frustum;
+use picking::Ray;
+use gpu;
+use scene::Sphere;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Perspective without far plane.
+    InfinitePerspective,
+    /// Perspective without far plane, mapping the near plane to a depth of 1
+    /// and infinity to 0, to be used with a `GREATER_EQUAL` depth test.
+    ///
+    /// The depths are meant for the [0, 1] clip range of `glClipControl`,
+    /// where they are not shifted into [-1, 1], so that the floating point
+    /// precision near 0 makes up for the precision lost with the distance.
+    ReverseZ,
+    /// Parallel projection of a view volume of the given height.
+    Orthographic { height: f32 },
+}
+
+impl Projection {
+    pub fn reversed_depth(&self) -> bool {
+        *self == Projection::ReverseZ
+    }
+
+    /// Value to clear the depth buffer with, which is the farthest depth.
+    pub fn depth_clear(&self) -> f32 {
+        if self.reversed_depth() { 0.0 } else { 1.0 }
+    }
+}
 
 pub struct Camera {
     position: Point3<f32>,
@@ -8,14 +39,19 @@ pub struct Camera {
     near: f32,
     far: f32,
     fov: f32,
+    aspect_ratio: f32,
+    projection: Projection,
 
     transform: Matrix4<f32>,
 }
 
 impl Camera {
-    pub fn new(scene_radius: f32) -> Camera {
-        let p = Point3::new(0., scene_radius * 0.1, scene_radius * -1.25 - 1.5);
-        let target = Point3::new(0., 0., scene_radius * -0.75);
+    /// Looks at the scene contained in `bounds` from its side.
+    pub fn new(bounds: &Sphere) -> Camera {
+        let scene_radius = bounds.radius;
+        let p = bounds.center +
+            Vector3::new(0., scene_radius * 0.1, scene_radius * -1.25 - 1.5);
+        let target = bounds.center + Vector3::new(0., 0., scene_radius * -0.75);
 
         Camera {
             position: p,
@@ -25,15 +61,18 @@ impl Camera {
             near: 0.01,
             far: scene_radius * 2.5 + 3.,
             fov: (70f32).to_radians(),
+            aspect_ratio: 1.,
+            projection: Projection::Perspective,
 
             transform: nalgebra::one(),
         }
     }
 
     pub fn update(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
         let view = self.compute_view();
         let projection = self.compute_projection(aspect_ratio);
-        self.transform = projection.as_matrix() * view;
+        self.transform = projection * view;
     }
 
     fn compute_view(&self) -> Matrix4<f32> {
@@ -44,15 +83,99 @@ impl Camera {
         ).to_homogeneous()
     }
 
-    fn compute_projection(&self, aspect_ratio: f32) -> Perspective3<f32> {
-        Perspective3::new(aspect_ratio, self.fov, self.near, self.far)
+    fn compute_projection(&self, aspect_ratio: f32) -> Matrix4<f32> {
+        let f = 1. / (self.fov * 0.5).tan();
+        let n = self.near;
+
+        match self.projection {
+            Projection::Perspective =>
+                *Perspective3::new(aspect_ratio, self.fov, self.near, self.far).as_matrix(),
+            Projection::InfinitePerspective => Matrix4::new(
+                f / aspect_ratio, 0., 0., 0.,
+                0., f, 0., 0.,
+                0., 0., -1., -2. * n,
+                0., 0., -1., 0.),
+            Projection::ReverseZ => Matrix4::new(
+                f / aspect_ratio, 0., 0., 0.,
+                0., f, 0., 0.,
+                0., 0., 0., n,
+                0., 0., -1., 0.),
+            Projection::Orthographic { height } => {
+                let (half_width, half_height) = (height * 0.5 * aspect_ratio, height * 0.5);
+                *Orthographic3::new(-half_width, half_width,
+                                    -half_height, half_height,
+                                    self.near, self.far).as_matrix()
+            }
+        }
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    /// Distance to the far plane, when there is one.
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    /// Projection × view matrix, as of the last `update`.
+    pub fn transform(&self) -> &Matrix4<f32> {
+        &self.transform
+    }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&self.transform)
+    }
+
+    /// World space ray going through `screen`, in normalized coordinates
+    /// from (-0.5, -0.5) at the bottom left to (0.5, 0.5) at the top right.
+    pub fn ray(&self, screen: Vector2<f32>) -> Ray {
+        if let Projection::Orthographic { height } = self.projection {
+            let (half_width, half_height) = (height * 0.5 * self.aspect_ratio, height * 0.5);
+            let origin = self.position +
+                         self.right() * (2. * screen.x * half_width) +
+                         self.up() * (2. * screen.y * half_height);
+            return Ray::new(origin, self.ahead());
+        }
+
+        let half_height = (self.fov * 0.5).tan();
+        let half_width = half_height * self.aspect_ratio;
+        let direction = self.ahead() +
+                        self.right() * (2. * screen.x * half_width) +
+                        self.up() * (2. * screen.y * half_height);
+        Ray::new(self.position, direction)
+    }
+
+    /// Right, up and ahead directions, scaled so that
+    /// `ahead + x * right + y * up` goes through the normalized device
+    /// coordinates `(x, y)`, to look up things at infinity.
+    ///
+    /// Orthographic projections use the field of view of the others,
+    /// otherwise the whole view would look in a single direction.
+    pub fn sky_basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let half_height = (self.fov * 0.5).tan();
+        let half_width = half_height * self.aspect_ratio;
+        (self.right() * half_width, self.up() * half_height, self.ahead())
+    }
+
+    /// Approximate radius in pixels of a sphere seen in a viewport
+    /// `viewport_height` pixels high.
+    pub fn projected_radius(&self, center: &Point3<f32>, radius: f32, viewport_height: f32) -> f32 {
+        if let Projection::Orthographic { height } = self.projection {
+            return radius / height * viewport_height;
+        }
+
+        let distance = nalgebra::distance(center, &self.position).max(radius);
+        let focal = viewport_height * 0.5 / (self.fov * 0.5).tan();
+        radius / distance * focal
     }
 
     pub fn gpu_transform(&self) -> [[f32; 4]; 4] {
-        use std::mem;
-        // FIXME
-        let ptr = self.transform.as_slice().as_ptr();
-        unsafe { *mem::transmute::<_, &[[f32; 4]; 4]>(ptr) }
+        gpu::mat4(&self.transform)
     }
 
     pub fn rotate(&mut self, r: UnitQuaternion<f32>) {
@@ -89,4 +212,41 @@ impl Camera {
     pub fn ahead(&self) -> Vector3<f32> {
         self.orientation * Vector3::z()
     }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.orientation * Vector3::y()
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.orientation * -Vector3::x()
+    }
+
+    pub fn translate(&mut self, t: Vector3<f32>) {
+        self.position += t;
+    }
+
+    pub fn position(&self) -> Point3<f32> {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: Point3<f32>) {
+        self.position = position;
+    }
+
+    pub fn orientation(&self) -> UnitQuaternion<f32> {
+        self.orientation
+    }
+
+    pub fn set_orientation(&mut self, orientation: UnitQuaternion<f32>) {
+        self.orientation = orientation;
+    }
+
+    /// Turns towards `target`, keeping the world up direction.
+    pub fn look_at(&mut self, target: &Point3<f32>) {
+        let direction = target - self.position;
+        if direction.norm_squared() > 0. {
+            self.orientation = UnitQuaternion::new_observer_frame(
+                &direction, &Vector3::y());
+        }
+    }
 }
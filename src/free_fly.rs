@@ -0,0 +1,100 @@
+use std::f32::consts::PI;
+use nalgebra::{Vector2, Vector3};
+use Camera;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    RollLeft,
+    RollRight,
+}
+
+const MOVEMENT_COUNT: usize = 8;
+
+/// Flies the camera freely, in all six degrees of freedom.
+pub struct FreeFlyController {
+    active: [bool; MOVEMENT_COUNT],
+    velocity: Vector3<f32>,
+    roll_velocity: f32,
+
+    /// Maximum translation speed, in units/s.
+    pub max_speed: f32,
+    /// Translation acceleration, in units/s².
+    pub acceleration: f32,
+    /// Fraction of the velocity lost per second when not accelerating.
+    pub damping: f32,
+    /// Maximum roll speed, in rad/s.
+    pub roll_speed: f32,
+    /// Maximum yaw and pitch speed, in rad/s,
+    /// reached when the mouse is on the edge of the screen.
+    pub look_speed: f32,
+}
+
+impl FreeFlyController {
+    pub fn new(max_speed: f32) -> Self {
+        FreeFlyController {
+            active: [false; MOVEMENT_COUNT],
+            velocity: Vector3::new(0., 0., 0.),
+            roll_velocity: 0.,
+
+            max_speed: max_speed,
+            acceleration: max_speed * 2.,
+            damping: 0.95,
+            roll_speed: 0.5 * PI,
+            look_speed: 2.0 * PI,
+        }
+    }
+
+    pub fn set(&mut self, movement: Movement, active: bool) {
+        self.active[movement as usize] = active;
+    }
+
+    fn axis(&self, positive: Movement, negative: Movement) -> f32 {
+        let p = if self.active[positive as usize] { 1. } else { 0. };
+        let n = if self.active[negative as usize] { 1. } else { 0. };
+        p - n
+    }
+
+    /// Turns towards the mouse, given in normalized screen coordinates
+    /// relative to the center of the screen.
+    pub fn look(&mut self, camera: &mut Camera, mouse: Vector2<f32>, delta: f32) {
+        let max_rotation = self.look_speed * delta;
+        camera.yaw(-mouse.x * max_rotation);
+        camera.pitch(mouse.y * max_rotation);
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, delta: f32) {
+        use self::Movement::*;
+
+        let local = Vector3::new(self.axis(Right, Left),
+                                 self.axis(Up, Down),
+                                 self.axis(Forward, Backward));
+        let local_norm = local.norm();
+        if local_norm > 0. {
+            let direction = camera.right() * local.x +
+                            camera.up() * local.y +
+                            camera.ahead() * local.z;
+            self.velocity += direction * (self.acceleration * delta / local_norm);
+        } else {
+            self.velocity *= (1. - self.damping).powf(delta);
+        }
+        let speed = self.velocity.norm();
+        if speed > self.max_speed {
+            self.velocity *= self.max_speed / speed;
+        }
+        camera.translate(self.velocity * delta);
+
+        let roll = self.axis(RollRight, RollLeft);
+        if roll != 0. {
+            self.roll_velocity = roll * self.roll_speed;
+        } else {
+            self.roll_velocity *= (1. - self.damping).powf(delta);
+        }
+        camera.roll(self.roll_velocity * delta);
+    }
+}
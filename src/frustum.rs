@@ -0,0 +1,144 @@
+use nalgebra::{Point3, Vector3, Vector4, Matrix4};
+
+/// Points `p` such that `normal.dot(p) + d >= 0` are inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_coefficients(c: Vector4<f32>) -> Plane {
+        let normal = Vector3::new(c.x, c.y, c.z);
+        let norm = normal.norm();
+        // planes at infinity have a null normal and are left as is
+        let scale = if norm > 0. { 1. / norm } else { 1. };
+        Plane {
+            normal: normal * scale,
+            d: c.w * scale,
+        }
+    }
+
+    /// Signed distance from the plane, positive inside.
+    pub fn distance(&self, p: &Point3<f32>) -> f32 {
+        self.normal.dot(&p.coords) + self.d
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far planes.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a projection × view matrix,
+    /// following OpenGL clip space conventions (-w <= x, y, z <= w).
+    ///
+    /// Gribb & Hartmann, "Fast Extraction of Viewing Frustum Planes
+    /// from the World-View-Projection Matrix", 2001.
+    pub fn from_matrix(m: &Matrix4<f32>) -> Frustum {
+        let row = |i: usize| Vector4::new(m[(i, 0)], m[(i, 1)], m[(i, 2)], m[(i, 3)]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        Frustum {
+            planes: [
+                Plane::from_coefficients(r3 + r0),
+                Plane::from_coefficients(r3 - r0),
+                Plane::from_coefficients(r3 + r1),
+                Plane::from_coefficients(r3 - r1),
+                Plane::from_coefficients(r3 + r2),
+                Plane::from_coefficients(r3 - r2),
+            ]
+        }
+    }
+
+    /// Conservative test: some spheres just outside of the corners are kept.
+    pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+        self.planes.iter().all(|p| p.distance(center) >= -radius)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3, Matrix4};
+    use super::*;
+
+    /// OpenGL `glOrtho(-2, 2, -1, 1, 1, 11)`.
+    fn orthographic() -> Matrix4<f32> {
+        Matrix4::new(0.5, 0., 0., 0.,
+                     0., 1., 0., 0.,
+                     0., 0., -0.2, -1.2,
+                     0., 0., 0., 1.)
+    }
+
+    /// OpenGL `gluPerspective(90, 1, 1, 3)`.
+    fn perspective() -> Matrix4<f32> {
+        Matrix4::new(1., 0., 0., 0.,
+                     0., 1., 0., 0.,
+                     0., 0., -2., -3.,
+                     0., 0., -1., 0.)
+    }
+
+    fn assert_plane(plane: &Plane, normal: Vector3<f32>, d: f32) {
+        assert!((plane.normal - normal).norm() < 1e-6 && (plane.d - d).abs() < 1e-6,
+                "{:?} instead of {:?}, {}", plane, normal, d);
+    }
+
+    #[test]
+    fn orthographic_planes() {
+        let f = Frustum::from_matrix(&orthographic());
+        assert_plane(&f.planes[0], Vector3::new(1., 0., 0.), 2.);
+        assert_plane(&f.planes[1], Vector3::new(-1., 0., 0.), 2.);
+        assert_plane(&f.planes[2], Vector3::new(0., 1., 0.), 1.);
+        assert_plane(&f.planes[3], Vector3::new(0., -1., 0.), 1.);
+        assert_plane(&f.planes[4], Vector3::new(0., 0., -1.), -1.);
+        assert_plane(&f.planes[5], Vector3::new(0., 0., 1.), 11.);
+    }
+
+    #[test]
+    fn perspective_planes() {
+        let f = Frustum::from_matrix(&perspective());
+        let s = 0.5f32.sqrt();
+        assert_plane(&f.planes[0], Vector3::new(s, 0., -s), 0.);
+        assert_plane(&f.planes[1], Vector3::new(-s, 0., -s), 0.);
+        assert_plane(&f.planes[2], Vector3::new(0., s, -s), 0.);
+        assert_plane(&f.planes[3], Vector3::new(0., -s, -s), 0.);
+        assert_plane(&f.planes[4], Vector3::new(0., 0., -1.), -1.);
+        assert_plane(&f.planes[5], Vector3::new(0., 0., 1.), 3.);
+    }
+
+    #[test]
+    fn infinite_far_plane() {
+        // far plane at infinity, near at 1
+        let m = Matrix4::new(1., 0., 0., 0.,
+                             0., 1., 0., 0.,
+                             0., 0., -1., -2.,
+                             0., 0., -1., 0.);
+        let f = Frustum::from_matrix(&m);
+        assert_eq!(f.planes[5].normal, Vector3::new(0., 0., 0.));
+        assert!(f.intersects_sphere(&Point3::new(0., 0., -1e6), 1.));
+    }
+
+    #[test]
+    fn spheres() {
+        for m in &[orthographic(), perspective()] {
+            let f = Frustum::from_matrix(m);
+            let inside = Point3::new(0., 0., -2.);
+            let radius = 0.25;
+            assert!(f.intersects_sphere(&inside, radius));
+
+            for plane in &f.planes {
+                let distance = plane.distance(&inside);
+                assert!(distance > radius);
+
+                let on_plane = inside - plane.normal * distance;
+                assert!(f.intersects_sphere(&on_plane, radius), "{:?}", plane);
+                let straddling = on_plane - plane.normal * radius * 0.5;
+                assert!(f.intersects_sphere(&straddling, radius), "{:?}", plane);
+                let outside = on_plane - plane.normal * radius * 1.5;
+                assert!(!f.intersects_sphere(&outside, radius), "{:?}", plane);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Conversions from nalgebra types to the layouts expected by shaders.
+//!
+//! Elements are read through `(row, column)` indexing, so nothing here
+//! depends on how nalgebra stores its matrices.
+
+use nalgebra::{Point3, Vector3, Vector4, Matrix4};
+
+/// GLSL `mat4`, as an array of columns: `m[column][row]`.
+pub fn mat4(m: &Matrix4<f32>) -> [[f32; 4]; 4] {
+    let mut out = [[0.; 4]; 4];
+    for (c, column) in out.iter_mut().enumerate() {
+        for (r, e) in column.iter_mut().enumerate() {
+            *e = m[(r, c)];
+        }
+    }
+    out
+}
+
+pub fn vec4(v: &Vector4<f32>) -> [f32; 4] {
+    [v.x, v.y, v.z, v.w]
+}
+
+/// Homogeneous coordinates of a point, with `w = 1`.
+pub fn point4(p: &Point3<f32>) -> [f32; 4] {
+    [p.x, p.y, p.z, 1.]
+}
+
+/// Homogeneous coordinates of a direction, with `w = 0`.
+pub fn direction4(v: &Vector3<f32>) -> [f32; 4] {
+    [v.x, v.y, v.z, 0.]
+}
+
+/// Layouts of uniform blocks declared with `layout(std140)`,
+/// which is the only layout all drivers agree on.
+///
+/// - scalars are aligned on 4 bytes, `vec2` on 8 bytes,
+///   `vec3` and `vec4` on 16 bytes;
+/// - array elements and matrix columns are aligned on 16 bytes,
+///   so a `float[N]` takes as much space as a `vec4[N]`
+///   and a `mat3` as much as three `vec4`s.
+///
+/// Rust structures mirroring such blocks should therefore only
+/// contain `[f32; 4]`-like fields, or fields padded by hand.
+pub mod std140 {
+    use nalgebra::{Vector3, Matrix3};
+
+    pub type Vec3 = [f32; 4];
+    pub type Mat3 = [[f32; 4]; 3];
+    /// Element of a `float[N]` array.
+    pub type Float = [f32; 4];
+
+    /// `vec3`, followed by 4 bytes of padding.
+    pub fn vec3(v: &Vector3<f32>) -> Vec3 {
+        [v.x, v.y, v.z, 0.]
+    }
+
+    /// `mat3`, as three padded columns: `m[column][row]`.
+    pub fn mat3(m: &Matrix3<f32>) -> Mat3 {
+        let mut out = [[0.; 4]; 3];
+        for (c, column) in out.iter_mut().enumerate() {
+            for (r, e) in column.iter_mut().take(3).enumerate() {
+                *e = m[(r, c)];
+            }
+        }
+        out
+    }
+
+    pub fn float(x: f32) -> Float {
+        [x, 0., 0., 0.]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3, Vector4, Matrix3, Matrix4};
+    use super::*;
+
+    #[test]
+    fn translation_in_last_column() {
+        let m = Matrix4::new(1., 0., 0., 2.,
+                             0., 1., 0., 3.,
+                             0., 0., 1., 4.,
+                             0., 0., 0., 1.);
+        let out = mat4(&m);
+        assert_eq!(out[3], [2., 3., 4., 1.]);
+        assert_eq!(out[0], [1., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn rotation_columns() {
+        // quarter turn around z, mapping x to y
+        let m = Matrix4::new(0., -1., 0., 0.,
+                             1., 0., 0., 0.,
+                             0., 0., 1., 0.,
+                             0., 0., 0., 1.);
+        assert_eq!(mat4(&m), [[0., 1., 0., 0.],
+                              [-1., 0., 0., 0.],
+                              [0., 0., 1., 0.],
+                              [0., 0., 0., 1.]]);
+        // column `c` is where the basis vector `c` goes
+        let x = m * Vector4::new(1., 0., 0., 0.);
+        assert_eq!(mat4(&m)[0], vec4(&x));
+    }
+
+    #[test]
+    fn homogeneous() {
+        assert_eq!(point4(&Point3::new(1., 2., 3.)), [1., 2., 3., 1.]);
+        assert_eq!(direction4(&Vector3::new(1., 2., 3.)), [1., 2., 3., 0.]);
+        assert_eq!(vec4(&Vector4::new(1., 2., 3., 4.)), [1., 2., 3., 4.]);
+    }
+
+    #[test]
+    fn std140_padding() {
+        let m = Matrix3::new(1., 2., 3.,
+                             4., 5., 6.,
+                             7., 8., 9.);
+        assert_eq!(std140::mat3(&m), [[1., 4., 7., 0.],
+                                      [2., 5., 8., 0.],
+                                      [3., 6., 9., 0.]]);
+        assert_eq!(std140::vec3(&Vector3::new(1., 2., 3.)), [1., 2., 3., 0.]);
+        assert_eq!(std140::float(5.), [5., 0., 0., 0.]);
+    }
+}
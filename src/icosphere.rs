@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::mem;
 use scene::Vertex;
+use mesh::Index;
 
 fn vertex(pos: [f32; 3]) -> Vertex {
     use std::f32::consts::{PI};
@@ -9,10 +10,11 @@ fn vertex(pos: [f32; 3]) -> Vertex {
     let u = if u < 0. { u + 1. } else { u };
     let v = pos[1].asin() / PI + 0.5;
 
-    Vertex::new(pos, [u, v])
+    // on the unit sphere, the normal is the position
+    Vertex::new(pos, pos, [u, v])
 }
 
-pub fn generate(recursion: u16) -> (Vec<Vertex>, Vec<u16>) {
+pub fn generate(recursion: u16) -> (Vec<Vertex>, Vec<Index>) {
     let face_count = 20 * 4usize.pow(recursion as u32);
     let edge_count = 3 * face_count / 2;
     // Euler's formula
@@ -42,7 +44,7 @@ pub fn generate(recursion: u16) -> (Vec<Vertex>, Vec<u16>) {
         vertex([ -v, 0.0, u]),
     ]);
 
-    let mut index_data: Vec<u16> = Vec::with_capacity(index_count);
+    let mut index_data: Vec<Index> = Vec::with_capacity(index_count);
     index_data.extend_from_slice(&[
         // 5 faces around point 0
         0, 11, 5,
@@ -92,7 +94,7 @@ pub fn generate(recursion: u16) -> (Vec<Vertex>, Vec<u16>) {
                             middle[1] * middle[1] +
                             middle[2] * middle[2]).sqrt();
                 
-                let index = vertex_data.len() as u16;
+                let index = vertex_data.len() as Index;
                 let v = vertex([middle[0]/norm, middle[1]/norm, middle[2]/norm]);
                 vertex_data.push(v);
 
@@ -123,7 +125,46 @@ pub fn generate(recursion: u16) -> (Vec<Vertex>, Vec<u16>) {
         }
     }
 
-    debug_assert!(vertex_data.len() == vertex_count);
-    debug_assert!(index_data.len() == index_count);
     (vertex_data, index_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use mesh;
+    use super::generate;
+
+    fn check(recursion: u16) {
+        let (vertices, indices) = generate(recursion);
+        let report = mesh::validate(&vertices, &indices);
+        let face_count = 20 * 4usize.pow(recursion as u32);
+        assert!(report.is_closed_sphere(), "{:?}", report);
+        assert_eq!(report.triangle_count, face_count);
+        assert_eq!(report.vertex_count, 2 + face_count / 2);
+        assert_eq!(report.edge_count, 3 * face_count / 2);
+    }
+
+    #[test]
+    fn icosahedron() {
+        check(0);
+    }
+
+    #[test]
+    fn subdivided_once() {
+        check(1);
+    }
+
+    #[test]
+    fn subdivided_twice() {
+        check(2);
+    }
+
+    #[test]
+    fn subdivided_three_times() {
+        check(3);
+    }
+
+    #[test]
+    fn subdivided_as_in_scene() {
+        check(4);
+    }
+}
@@ -0,0 +1,382 @@
+use std::cmp;
+use std::f32::consts::PI;
+use rand::{Rng, XorShiftRng};
+use rand::distributions::{IndependentSample, Range};
+use nalgebra::{self, Point3, Vector3};
+use scene::Sphere;
+
+/// Where the spheres of a scene are.
+pub trait InstanceLayout {
+    fn name(&self) -> &'static str;
+
+    /// Places `count` spheres.
+    fn place(&self, count: usize, rng: &mut XorShiftRng) -> Vec<Sphere>;
+
+    /// Sphere containing `spheres`, as placed by `place`.
+    fn bounds(&self, spheres: &[Sphere]) -> Sphere;
+
+    /// Whether some spheres may be turned into moons, moving them
+    /// away from where they were placed.
+    fn moons(&self) -> bool { false }
+}
+
+pub const NAMES: &'static [&'static str] = &["ring", "grid", "spiral", "poisson"];
+
+pub fn from_name(name: &str) -> Option<Box<InstanceLayout>> {
+    match name {
+        "ring" => Some(Box::new(Ring)),
+        "grid" => Some(Box::new(Grid { spacing: 4. })),
+        "spiral" => Some(Box::new(SpiralGalaxy { arms: 2, turns: 1.5, spacing: 4. })),
+        "poisson" => Some(Box::new(PoissonDisk { min_distance: 4. })),
+        _ => None,
+    }
+}
+
+fn random_radius(rng: &mut XorShiftRng) -> f32 {
+    Range::new(0.5, 1.5).ind_sample(rng)
+}
+
+const MAX_RADIUS: f32 = 1.5;
+
+fn origin() -> Point3<f32> {
+    Point3::new(0., 0., 0.)
+}
+
+/// Jittered ring of radius `count * 4 / 2π` in the xz plane.
+pub struct Ring;
+
+impl Ring {
+    fn radius(count: usize) -> f32 {
+        (count as f32 * 4.0) / (2.0 * PI)
+    }
+}
+
+impl InstanceLayout for Ring {
+    fn name(&self) -> &'static str { "ring" }
+
+    fn moons(&self) -> bool { true }
+
+    fn place(&self, count: usize, rng: &mut XorShiftRng) -> Vec<Sphere> {
+        let ring_radius = Ring::radius(count);
+
+        (0..count).map(|i| {
+            let angle = i as f32 / count as f32 * (2.0 * PI);
+            let position = Vector3::new(
+                angle.cos() * ring_radius,
+                0.0,
+                angle.sin() * ring_radius);
+
+            let radius = random_radius(rng);
+            let remaining = 2.0 - radius;
+
+            let range = Range::new(-remaining, remaining);
+            let displacement = Vector3::new(
+                range.ind_sample(rng),
+                range.ind_sample(rng),
+                range.ind_sample(rng),
+            );
+
+            Sphere {
+                center: Point3::from_coordinates(position + displacement),
+                radius: radius,
+            }
+        }).collect()
+    }
+
+    fn bounds(&self, spheres: &[Sphere]) -> Sphere {
+        // the displacement and the radius add up to at most 2 on each axis
+        Sphere { center: origin(), radius: Ring::radius(spheres.len()) + 2. * 3f32.sqrt() }
+    }
+}
+
+/// Cubic grid centered on the origin.
+pub struct Grid {
+    /// Distance between the centers of two neighbours.
+    pub spacing: f32,
+}
+
+impl Grid {
+    fn side(count: usize) -> usize {
+        let mut side = (count as f32).cbrt().ceil() as usize;
+        while side * side * side < count { side += 1; }
+        side
+    }
+}
+
+impl InstanceLayout for Grid {
+    fn name(&self) -> &'static str { "grid" }
+
+    fn place(&self, count: usize, rng: &mut XorShiftRng) -> Vec<Sphere> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let side = Grid::side(count);
+        let offset = (side - 1) as f32 * 0.5;
+
+        (0..count).map(|i| {
+            let (x, y, z) = (i % side, (i / side) % side, i / (side * side));
+            Sphere {
+                center: Point3::new((x as f32 - offset) * self.spacing,
+                                    (y as f32 - offset) * self.spacing,
+                                    (z as f32 - offset) * self.spacing),
+                radius: random_radius(rng),
+            }
+        }).collect()
+    }
+
+    fn bounds(&self, spheres: &[Sphere]) -> Sphere {
+        let half_extent = Grid::side(spheres.len()).saturating_sub(1) as f32 * 0.5 * self.spacing;
+        Sphere { center: origin(), radius: half_extent * 3f32.sqrt() + MAX_RADIUS }
+    }
+}
+
+/// Logarithmic spiral arms `r = a·e^(bθ)` in the xz plane,
+/// scattered more and more as they go out.
+pub struct SpiralGalaxy {
+    pub arms: usize,
+    /// Turns made by each arm.
+    pub turns: f32,
+    /// Average distance between two neighbours.
+    pub spacing: f32,
+}
+
+/// `b` in `r = a·e^(bθ)`.
+const SPIRAL_GROWTH: f32 = 0.3;
+
+impl SpiralGalaxy {
+    fn radius(&self, count: usize) -> f32 {
+        // enough disc area for each sphere
+        self.spacing * (count as f32 / PI).sqrt() * 1.5
+    }
+}
+
+impl InstanceLayout for SpiralGalaxy {
+    fn name(&self) -> &'static str { "spiral" }
+
+    fn place(&self, count: usize, rng: &mut XorShiftRng) -> Vec<Sphere> {
+        let galaxy_radius = self.radius(count);
+        let max_angle = self.turns * 2. * PI;
+        let a = galaxy_radius / (SPIRAL_GROWTH * max_angle).exp();
+        let unit = Range::new(-1f32, 1.);
+
+        (0..count).map(|i| {
+            let arm = i % self.arms;
+            let t = (i / self.arms) as f32 / cmp::max(count / self.arms, 1) as f32;
+            let theta = t * max_angle;
+            let r = a * (SPIRAL_GROWTH * theta).exp();
+            let angle = theta + arm as f32 * 2. * PI / self.arms as f32;
+
+            let scatter = self.spacing * (0.5 + t * 2.);
+            Sphere {
+                center: Point3::new(angle.cos() * r + unit.ind_sample(rng) * scatter,
+                                    unit.ind_sample(rng) * scatter * 0.25,
+                                    angle.sin() * r + unit.ind_sample(rng) * scatter),
+                radius: random_radius(rng),
+            }
+        }).collect()
+    }
+
+    fn bounds(&self, spheres: &[Sphere]) -> Sphere {
+        let max_scatter = Vector3::new(1., 0.25, 1.).norm() * self.spacing * 2.5;
+        Sphere {
+            center: origin(),
+            radius: self.radius(spheres.len()) + max_scatter + MAX_RADIUS,
+        }
+    }
+}
+
+/// Poisson-disk sampling of a cube centered on the origin:
+/// spheres are randomly placed but never closer than `min_distance`.
+///
+/// Bridson, "Fast Poisson Disk Sampling in Arbitrary Dimensions", 2007.
+pub struct PoissonDisk {
+    pub min_distance: f32,
+}
+
+/// Candidates tried around a point before giving up on it.
+const POISSON_CANDIDATES: usize = 30;
+
+impl PoissonDisk {
+    fn side(&self, count: usize) -> f32 {
+        // Bridson's sampling fits about one point per r³, leave room for one per 2 r³
+        self.min_distance * (count as f32 * 2.).cbrt()
+    }
+
+    fn sample(&self, count: usize, side: f32, rng: &mut XorShiftRng) -> Vec<Point3<f32>> {
+        let r = self.min_distance;
+        let cell = r / 3f32.sqrt();
+        let n = (side / cell).ceil() as usize;
+        let half = side * 0.5;
+        let mut grid: Vec<Option<usize>> = vec![None; n * n * n];
+        let cell_of = |p: &Point3<f32>| {
+            let c = |x: f32| cmp::min(((x + half) / cell) as usize, n - 1);
+            (c(p.x), c(p.y), c(p.z))
+        };
+
+        let mut points = Vec::with_capacity(count);
+        let mut active = Vec::new();
+        let coordinate = Range::new(-half, half);
+        let first = Point3::new(coordinate.ind_sample(rng),
+                                coordinate.ind_sample(rng),
+                                coordinate.ind_sample(rng));
+        let (x, y, z) = cell_of(&first);
+        grid[x + n * (y + n * z)] = Some(0);
+        points.push(first);
+        active.push(0);
+
+        let unit = Range::new(-1f32, 1.);
+        let distance = Range::new(r, 2. * r);
+        while !active.is_empty() && points.len() < count {
+            let a = rng.gen_range(0, active.len());
+            let center = points[active[a]];
+
+            let mut found = false;
+            for _ in 0..POISSON_CANDIDATES {
+                let mut direction = Vector3::new(unit.ind_sample(rng),
+                                                 unit.ind_sample(rng),
+                                                 unit.ind_sample(rng));
+                let norm = direction.norm();
+                if norm == 0. || norm > 1. { continue; }
+                direction /= norm;
+
+                let p = center + direction * distance.ind_sample(rng);
+                if p.x.abs() > half || p.y.abs() > half || p.z.abs() > half {
+                    continue;
+                }
+
+                let (x, y, z) = cell_of(&p);
+                let range = |c: usize| c.saturating_sub(2)..cmp::min(c + 3, n);
+                let mut free = true;
+                'neighbours: for nz in range(z) {
+                    for ny in range(y) {
+                        for nx in range(x) {
+                            if let Some(o) = grid[nx + n * (ny + n * nz)] {
+                                if nalgebra::distance_squared(&points[o], &p) < r * r {
+                                    free = false;
+                                    break 'neighbours;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                if free {
+                    grid[x + n * (y + n * z)] = Some(points.len());
+                    active.push(points.len());
+                    points.push(p);
+                    found = true;
+                    break;
+                }
+            }
+
+            if !found {
+                active.swap_remove(a);
+            }
+        }
+
+        points
+    }
+}
+
+impl InstanceLayout for PoissonDisk {
+    fn name(&self) -> &'static str { "poisson" }
+
+    fn place(&self, count: usize, rng: &mut XorShiftRng) -> Vec<Sphere> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let mut side = self.side(count);
+        loop {
+            let points = self.sample(count, side, rng);
+            if points.len() == count {
+                return points.into_iter()
+                    .map(|p| Sphere { center: p, radius: random_radius(rng) })
+                    .collect();
+            }
+            // the cube was too small, which is unlikely
+            side *= 1.1;
+        }
+    }
+
+    fn bounds(&self, spheres: &[Sphere]) -> Sphere {
+        // `place` may have grown the cube any number of times
+        let radius = spheres.iter()
+            .map(|s| s.center.coords.norm() + s.radius)
+            .fold(0., f32::max);
+        Sphere { center: origin(), radius: radius }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra;
+    use rand::{SeedableRng, XorShiftRng};
+    use super::*;
+
+    fn rng() -> XorShiftRng {
+        XorShiftRng::from_seed([1, 2, 3, 4])
+    }
+
+    fn layouts() -> Vec<Box<InstanceLayout>> {
+        NAMES.iter().map(|name| from_name(name).unwrap()).collect()
+    }
+
+    #[test]
+    fn names() {
+        for (layout, &name) in layouts().iter().zip(NAMES) {
+            assert_eq!(layout.name(), name);
+        }
+        assert!(from_name("cube").is_none());
+    }
+
+    #[test]
+    fn no_spheres() {
+        for layout in layouts() {
+            assert!(layout.place(0, &mut rng()).is_empty(), "{}", layout.name());
+            assert!(layout.bounds(&[]).radius >= 0., "{}", layout.name());
+        }
+    }
+
+    #[test]
+    fn spheres_within_bounds() {
+        for layout in layouts() {
+            for &count in &[1, 2, 27, 100] {
+                let spheres = layout.place(count, &mut rng());
+                let bounds = layout.bounds(&spheres);
+                assert_eq!(spheres.len(), count, "{}", layout.name());
+                for sphere in &spheres {
+                    let distance = nalgebra::distance(&bounds.center, &sphere.center);
+                    assert!(distance + sphere.radius <= bounds.radius + 1e-3,
+                            "{} with {} spheres", layout.name(), count);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn poisson_min_distance() {
+        let layout = PoissonDisk { min_distance: 4. };
+        let spheres = layout.place(200, &mut rng());
+        for (i, a) in spheres.iter().enumerate() {
+            for b in &spheres[i + 1..] {
+                assert!(nalgebra::distance(&a.center, &b.center) >= 4.);
+            }
+        }
+    }
+
+    #[test]
+    fn poisson_grows_until_the_spheres_fit() {
+        let layout = PoissonDisk { min_distance: 4. };
+        // even packed as densely as possible, a cube 40% as wide cannot hold them
+        assert!(layout.sample(50, layout.side(50) * 0.4, &mut rng()).len() < 50);
+
+        let spheres = layout.place(50, &mut rng());
+        assert_eq!(spheres.len(), 50);
+        // the bounds touch the farthest sphere
+        let bounds = layout.bounds(&spheres);
+        let farthest = spheres.iter()
+            .map(|s| nalgebra::distance(&bounds.center, &s.center) + s.radius)
+            .fold(0., f32::max);
+        assert!((farthest - bounds.radius).abs() < 1e-4);
+    }
+}
@@ -1,5 +1,6 @@
 #[macro_use]
 pub extern crate gfx;
+extern crate gfx_core;
 extern crate gfx_device_gl;
 extern crate gfx_window_glutin;
 extern crate glutin;
@@ -14,30 +15,152 @@ extern crate rayon;
 
 pub mod scene;
 mod camera;
+mod orbit;
+mod free_fly;
+mod path;
+mod frustum;
+mod picking;
+pub mod layout;
+pub mod animation;
+mod atmosphere;
+mod skybox;
+mod allocator;
+mod pool;
+pub mod ring;
+pub mod virtual_texture;
+pub mod strategy;
+pub mod bench;
+pub mod null;
+mod lighting;
 mod texture;
 mod icosphere;
+pub mod mesh;
+pub mod gpu;
 
 pub use scene::Scene;
-pub use camera::Camera;
-pub use texture::generate as generate_texture;
+pub use camera::{Camera, Projection};
+pub use orbit::OrbitController;
+pub use free_fly::{FreeFlyController, Movement};
+pub use path::{CameraPath, Keyframe, Interpolation, PathRecorder};
+pub use frustum::{Frustum, Plane};
+pub use picking::{Ray, pick};
+pub use layout::InstanceLayout;
+pub use lighting::Lighting;
+pub use atmosphere::Atmosphere;
+pub use skybox::{Skybox, Star};
+pub use allocator::{LayerAllocator, Slot};
+pub use pool::{TexturePool, Residency};
+pub use strategy::Strategy;
+pub use texture::{generate as generate_texture, Recipe};
 pub use icosphere::generate as generate_icosphere;
 
-use std::env;
+use std::{cmp, env};
+use std::path::PathBuf;
 use std::f32::consts::PI;
 use nalgebra::{Vector2, Vector3, UnitQuaternion};
 use time::precise_time_s;
 use gfx::Device;
 use scene::{ColorFormat, DepthFormat};
 
-pub fn run<I>(title: &str)
-    where I: scene::Impl<gfx_device_gl::Resources>
-{
-    let mut args = env::args().skip(1);
+/// Command line options:
+/// `[sphere_count [texture_size]] [--strategy name] [--list] [--layout name]
+/// [--record path] [--play path] [--max-layers n] [--textures n] [--bench script]`.
+pub struct Options {
+    /// One of `strategy::NAMES`.
+    pub strategy: String,
+    /// Lists the strategies instead of running.
+    pub list: bool,
+    pub sphere_count: usize,
+    pub texture_size: usize,
+    /// One of `layout::NAMES`.
+    pub layout: String,
+    /// Where to save camera paths recorded with `K`.
+    pub record: PathBuf,
+    /// Camera path to play back instead of interactive control.
+    pub play: Option<PathBuf>,
+    /// Layers per texture array, instead of the driver limit.
+    pub max_layers: Option<usize>,
+    /// Textures resident at once, one per sphere by default.
+    pub textures: Option<usize>,
+    /// Benchmark script to run headless instead, see `bench::Script`.
+    pub bench: Option<PathBuf>,
+    /// Draws a skybox behind the planets.
+    pub sky: bool,
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        let mut options = Options {
+            strategy: String::from("staging"),
+            list: false,
+            sphere_count: 64,
+            texture_size: 128,
+            layout: String::from("ring"),
+            record: PathBuf::from("camera.path"),
+            play: None,
+            max_layers: None,
+            textures: None,
+            bench: None,
+            sky: false,
+        };
 
-    let sphere_count: usize = args.next()
-        .map(|s| s.parse().expect("expected number of spheres")).unwrap_or(64);
-    let texture_size: usize = args.next()
-        .map(|s| s.parse().expect("expected texture size")).unwrap_or(128);
+        let mut args = env::args().skip(1);
+        let mut positional = 0;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .unwrap_or_else(|| panic!("expected a value after {}", name));
+            match &arg[..] {
+                "--strategy" => options.strategy = value(&arg),
+                "--list" => options.list = true,
+                "--layout" => options.layout = value(&arg),
+                "--record" => options.record = PathBuf::from(value(&arg)),
+                "--play" => options.play = Some(PathBuf::from(value(&arg))),
+                "--max-layers" => options.max_layers = Some(value(&arg).parse()
+                    .expect("expected number of layers")),
+                "--textures" => options.textures = Some(value(&arg).parse()
+                    .expect("expected number of textures")),
+                "--bench" => options.bench = Some(PathBuf::from(value(&arg))),
+                "--sky" => options.sky = true,
+                _ => {
+                    match positional {
+                        0 => options.sphere_count = arg.parse()
+                            .expect("expected number of spheres"),
+                        1 => options.texture_size = arg.parse()
+                            .expect("expected texture size"),
+                        _ => panic!("unexpected argument: {}", arg),
+                    }
+                    positional += 1;
+                }
+            }
+        }
+
+        options
+    }
+}
+
+pub fn run() {
+    let options = Options::from_args();
+    if options.list {
+        for name in strategy::NAMES {
+            let strategy = Strategy::<gfx_device_gl::Resources>::from_name(name).unwrap();
+            println!("{}: {}", name, strategy.description());
+        }
+        return;
+    }
+    if let Some(ref script) = options.bench {
+        bench::run(script);
+        return;
+    }
+    let strategy = Strategy::from_name(&options.strategy)
+        .unwrap_or_else(|| panic!("unknown strategy {}, expected one of {:?}",
+                                  options.strategy, strategy::NAMES));
+    println!("strategy: {}", strategy.description());
+
+    let sphere_count = options.sphere_count;
+    let texture_size = options.texture_size;
+    let layout = layout::from_name(&options.layout)
+        .unwrap_or_else(|| panic!("unknown layout {}, expected one of {:?}",
+                                  options.layout, layout::NAMES));
 
     let gl_version = glutin::GlRequest::GlThenGles {
         opengl_version: (3, 2),
@@ -45,23 +168,45 @@ pub fn run<I>(title: &str)
     };
     let wb = glutin::WindowBuilder::new()
         .with_gl(gl_version)
-        .with_title(format!("Primus Polygoni -- {}", title));
+        .with_title("Primus Polygoni");
     let (window, mut device, mut factory, main_color, main_depth) =
         gfx_window_glutin::init::<ColorFormat, DepthFormat>(wb);
     let (width, height) = window.get_inner_size_points().unwrap();
     let mut aspect_ratio = width as f32 / height as f32;
 
+    let max_layers = options.max_layers.unwrap_or_else(|| max_array_layers(&mut device));
+    println!("{} layers per texture array", max_layers);
+    let clip_control = device.get_info().is_version_or_extension_supported(
+        4, 5, "GL_ARB_clip_control");
+
     let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
     let mut update_encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
 
-    let mut scene = Scene::<_, I>::new(sphere_count,
-                                       texture_size,
-                                       main_color,
-                                       main_depth,
-                                       &mut factory,
-                                       &mut encoder);
+    let mut scene = Scene::new(strategy,
+                               sphere_count,
+                               texture_size,
+                               options.textures.unwrap_or(sphere_count),
+                               max_layers,
+                               &*layout,
+                               options.sky,
+                               main_color,
+                               main_depth,
+                               &mut factory,
+                               &mut encoder);
 
     let mut mouse = Vector2::new(0., 0.);
+    let mut mouse_drag = Vector2::new(0., 0.);
+    let mut scroll = 0.;
+    let mut controller = match options.play {
+        Some(ref path) => {
+            let camera_path = CameraPath::load(path)
+                .expect("could not load camera path");
+            println!("playing {} ({} s)", path.display(), camera_path.duration());
+            Controller::Playback(camera_path, precise_time_s())
+        }
+        None => Controller::Ring,
+    };
+    let mut recorder: Option<(PathRecorder, f64)> = None;
     let mut head_spinning = false;
     let mut going_left = false;
     let mut going_right = false;
@@ -82,12 +227,18 @@ pub fn run<I>(title: &str)
                     aspect_ratio = w as f32 / h as f32;
                     gfx_window_glutin::update_views(
                         &window,
-                        &mut scene.data.color_target,
-                        &mut scene.data.depth_target
+                        &mut scene.color_target,
+                        &mut scene.depth_target
                     );
                 }
                 KeyboardInput(state, _, Some(key)) => {
                     use glutin::VirtualKeyCode::*;
+                    if let Controller::FreeFly(ref mut free_fly) = controller {
+                        if let Some(movement) = free_fly_movement(key) {
+                            free_fly.set(movement, state == Pressed);
+                            continue;
+                        }
+                    }
                     match key {
                         Q | Left => going_left = state == Pressed,
                         D | Right => going_right = state == Pressed,
@@ -96,56 +247,226 @@ pub fn run<I>(title: &str)
                             async = !async;
                             println!("♥--♥ async = {} ♥--♥", async);
                         }
+                        O if state == Released => {
+                            controller = match controller {
+                                Controller::Orbit(..) => Controller::Ring,
+                                _ => scene.nearest_sphere(&scene.camera.position())
+                                    .map(|i| Controller::Orbit(
+                                        OrbitController::new(&scene.camera,
+                                                             &scene.spheres[i]), i))
+                                    .unwrap_or(Controller::Ring),
+                            };
+                            println!("camera: {}", controller.name());
+                        }
+                        F if state == Released => {
+                            controller = match controller {
+                                Controller::FreeFly(_) => Controller::Ring,
+                                _ => Controller::FreeFly(
+                                    FreeFlyController::new(scene.scene_radius * 0.25 + 2.)),
+                            };
+                            println!("camera: {}", controller.name());
+                        }
+                        P if state == Released => {
+                            let projection = match scene.camera.projection() {
+                                Projection::Perspective => Projection::InfinitePerspective,
+                                Projection::InfinitePerspective if clip_control =>
+                                    Projection::ReverseZ,
+                                Projection::InfinitePerspective |
+                                Projection::ReverseZ => Projection::Orthographic {
+                                    height: scene.scene_radius * 2.
+                                },
+                                Projection::Orthographic { .. } => Projection::Perspective,
+                            };
+                            if clip_control {
+                                set_clip_depth(&mut device, projection.reversed_depth());
+                            }
+                            scene.camera.set_projection(projection);
+                            println!("projection: {:?}", projection);
+                        }
+                        E if state == Released => {
+                            if let Some(selected) = scene.selected() {
+                                let mut appearance = scene.appearances[selected];
+                                appearance.emissive = !appearance.emissive;
+                                scene.set_appearance(selected, appearance);
+                                println!("planet {} emissive: {}", selected, appearance.emissive);
+                            }
+                        }
+                        T if state == Released => {
+                            let enabled = !scene.virtual_texture.enabled;
+                            scene.virtual_texture.enabled = enabled;
+                            println!("virtual texturing: {}", enabled);
+                        }
+                        U if state == Released => {
+                            let next = scene.implementation.next();
+                            println!("strategy: {}", next.description());
+                            scene.set_implementation(next, &mut factory);
+                            reset = true;
+                        }
+                        H if state == Released => {
+                            scene.atmosphere_pass = !scene.atmosphere_pass;
+                            println!("atmospheres: {}", scene.atmosphere_pass);
+                        }
+                        L if state == Released => {
+                            scene.lighting.specular = if scene.lighting.specular > 0. {
+                                0.
+                            } else {
+                                Lighting::default().specular
+                            };
+                            println!("specular: {}", scene.lighting.specular);
+                        }
+                        M if state == Released => {
+                            scene.animating = !scene.animating;
+                            println!("animation: {}", scene.animating);
+                        }
+                        V if state == Released => {
+                            let culling = !scene.culling();
+                            scene.set_culling(culling);
+                            println!("frustum culling: {}", culling);
+                        }
+                        K if state == Released => {
+                            recorder = match recorder.take() {
+                                Some((recorder, _)) => {
+                                    let camera_path = recorder.finish();
+                                    match camera_path.save(&options.record) {
+                                        Ok(()) => println!("saved {} ({} s)",
+                                                           options.record.display(),
+                                                           camera_path.duration()),
+                                        Err(e) => println!("could not save {}: {}",
+                                                           options.record.display(), e),
+                                    }
+                                    None
+                                }
+                                None => {
+                                    println!("recording camera path");
+                                    Some((PathRecorder::new(), precise_time_s()))
+                                }
+                            };
+                        }
+                        N | B if state == Released => {
+                            if let Controller::Orbit(ref mut orbit, ref mut target) = controller {
+                                let count = scene.spheres.len();
+                                *target = if key == N {
+                                    (*target + 1) % count
+                                } else {
+                                    (*target + count - 1) % count
+                                };
+                                orbit.set_target(&scene.spheres[*target]);
+                            }
+                        }
                         _ => {}
                     }
                 },
                 MouseMoved(x, y) => {
-                    let (w, h, _, _) = scene.data.color_target.get_dimensions();
-                    mouse = Vector2::new((x as f32 / w as f32) - 0.5,
-                                         0.5 - (y as f32 / h as f32));
+                    let (w, h, _, _) = scene.color_target.get_dimensions();
+                    let position = Vector2::new((x as f32 / w as f32) - 0.5,
+                                                0.5 - (y as f32 / h as f32));
+                    if head_spinning {
+                        mouse_drag += position - mouse;
+                    }
+                    mouse = position;
+                }
+                MouseWheel(delta, _) => {
+                    use glutin::MouseScrollDelta::*;
+                    scroll += match delta {
+                        LineDelta(_, y) => y,
+                        PixelDelta(_, y) => y / 20.,
+                    };
                 }
                 MouseInput(state, Left) => head_spinning = state == Pressed,
+                MouseInput(Released, Right) => {
+                    let ray = scene.camera.ray(mouse);
+                    scene.select(pick(&ray, &scene.spheres).map(|(i, _)| i));
+                    match scene.selected() {
+                        Some(i) => println!("selected sphere {}: {}", i, scene.recipe(i)),
+                        None => println!("no sphere selected"),
+                    }
+                }
                 _ => {}
             }
         }
 
         let now = precise_time_s() as f32;
         let delta = now - marker;
-        fps_counter.update(delta).map(|fps| println!("{} fps", fps));
+        fps_counter.update(delta).map(|fps| println!("{} fps ({}/{} spheres drawn)",
+                                                     fps,
+                                                     scene.drawn_count(),
+                                                     scene.sphere_count));
         marker = now;
 
-        if head_spinning {
-            let max_rotation = 2.0 * PI * delta;
-            scene.camera.rotate(UnitQuaternion::new(
-                Vector3::y() * (-mouse.x * max_rotation)
-            ));
-            scene.camera.pitch(mouse.y * max_rotation);
-        }
+        scene.animate(delta);
+        match controller {
+            Controller::Ring => {
+                if head_spinning {
+                    let max_rotation = 2.0 * PI * delta;
+                    scene.camera.rotate(UnitQuaternion::new(
+                        Vector3::y() * (-mouse.x * max_rotation)
+                    ));
+                    scene.camera.pitch(mouse.y * max_rotation);
+                }
 
-        let speed = 0.5 * PI;
-        if going_left { scene.camera.move_left(speed * delta); }
-        if going_right { scene.camera.move_right(speed * delta); }
-        if reset {
-            print!("generating textures ... ");
+                let speed = 0.5 * PI;
+                if going_left { scene.camera.move_left(speed * delta); }
+                if going_right { scene.camera.move_right(speed * delta); }
+            }
+            Controller::Orbit(ref mut orbit, target) => {
+                orbit.follow(&scene.spheres[target]);
+                orbit.drag(mouse_drag);
+                orbit.zoom(scroll);
+                orbit.update(&mut scene.camera, delta);
+            }
+            Controller::FreeFly(ref mut free_fly) => {
+                if head_spinning {
+                    free_fly.look(&mut scene.camera, mouse, delta);
+                }
+                free_fly.update(&mut scene.camera, delta);
+            }
+            Controller::Playback(ref camera_path, ref mut start) => {
+                let time = (precise_time_s() - *start) as f32;
+                if !camera_path.apply(&mut scene.camera, time) {
+                    println!("camera path finished, restarting");
+                    *start = precise_time_s();
+                }
+            }
+        }
+        if let Some((ref mut recorder, start)) = recorder {
+            recorder.record(&scene.camera, (precise_time_s() - start) as f32);
+        }
+        mouse_drag = Vector2::new(0., 0.);
+        scroll = 0.;
+        if reset || scene.textures_stale() {
             let before = precise_time_s() as f32;
-            scene.generate_textures(&mut update_encoder, &mut factory);
+            let layers = if reset {
+                print!("generating textures ... ");
+                scene.generate_textures(&mut update_encoder, &mut factory, &mut device)
+            } else {
+                // the texture pool gave slots to other spheres
+                print!("updating pooled textures ({} resident) ... ",
+                       scene.pool.resident_count());
+                scene.update_textures(&mut update_encoder, &mut factory, &mut device)
+            };
             let after = precise_time_s() as f32;
             reset = false;
-            println!("took {} ms", (after - before) * 1_000.);
+            println!("took {} ms for {} layers ({} ms per layer)",
+                     (after - before) * 1_000., layers,
+                     (after - before) * 1_000. / cmp::max(layers, 1) as f32);
+            let fence_wait = scene.implementation.fence_wait();
+            if fence_wait > 0. {
+                println!("waited {} ms on fences", fence_wait * 1_000.);
+            }
 
-            if async {
-                update_encoder.flush(&mut device);
-            } else {
-                let fence = update_encoder.fenced_flush_no_reset(&mut device, None)
-                    .unwrap();
+            let fence = update_encoder.fenced_flush_no_reset(&mut device, None)
+                .unwrap();
+            if !async {
                 device.wait_fence(&fence);
-                update_encoder.reset();
-            }    
+            }
+            update_encoder.reset();
+            scene.implementation.submitted(fence);
             println!("update time: {} ms", (precise_time_s() as f32 - after) * 1_000.);
         }
 
-        encoder.clear(&scene.data.color_target, [0.1, 0.2, 0.3, 1.0]);
-        encoder.clear_depth(&scene.data.depth_target, 1.0);
+        encoder.clear(&scene.color_target, [0.0, 0.0, 0.0, 1.0]);
+        encoder.clear_depth(&scene.depth_target,
+                            scene.camera.projection().depth_clear());
         scene.render(aspect_ratio, &mut encoder);
         encoder.flush(&mut device);
         window.swap_buffers().unwrap();
@@ -153,6 +474,61 @@ pub fn run<I>(title: &str)
     }
 }
 
+/// `GL_MAX_ARRAY_TEXTURE_LAYERS`, which is at least 256 since OpenGL 3.0.
+fn max_array_layers(device: &mut gfx_device_gl::Device) -> usize {
+    let mut layers = 0;
+    unsafe {
+        device.with_gl(|gl| gl.GetIntegerv(gfx_device_gl::gl::MAX_ARRAY_TEXTURE_LAYERS,
+                                           &mut layers));
+    }
+    layers as usize
+}
+
+/// Switches between OpenGL's [-1, 1] clip depth range and the [0, 1] one
+/// reverse-Z is meant for, which needs OpenGL 4.5 or `GL_ARB_clip_control`.
+fn set_clip_depth(device: &mut gfx_device_gl::Device, zero_to_one: bool) {
+    use gfx_device_gl::gl;
+
+    let depth = if zero_to_one { gl::ZERO_TO_ONE } else { gl::NEGATIVE_ONE_TO_ONE };
+    unsafe {
+        device.with_gl(|gl| gl.ClipControl(gl::LOWER_LEFT, depth));
+    }
+}
+
+enum Controller {
+    Ring,
+    Orbit(OrbitController, usize),
+    FreeFly(FreeFlyController),
+    /// Follows a camera path, started at the given time.
+    Playback(CameraPath, f64),
+}
+
+impl Controller {
+    fn name(&self) -> &'static str {
+        match *self {
+            Controller::Ring => "ring",
+            Controller::Orbit(..) => "orbit",
+            Controller::FreeFly(_) => "free fly",
+            Controller::Playback(..) => "playback",
+        }
+    }
+}
+
+fn free_fly_movement(key: glutin::VirtualKeyCode) -> Option<Movement> {
+    use glutin::VirtualKeyCode::*;
+    match key {
+        Z | Up => Some(Movement::Forward),
+        S | Down => Some(Movement::Backward),
+        Q | Left => Some(Movement::Left),
+        D | Right => Some(Movement::Right),
+        Space => Some(Movement::Up),
+        LShift => Some(Movement::Down),
+        W => Some(Movement::RollLeft),
+        C => Some(Movement::RollRight),
+        _ => None,
+    }
+}
+
 pub struct FpsCounter {
     accumulator: f32,
     frames: f32,
@@ -0,0 +1,73 @@
+use nalgebra::{Vector3, Vector4};
+use gpu;
+
+/// Directional sun with an ambient term, following Lambert for diffuse
+/// and Blinn-Phong for specular reflection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lighting {
+    /// Direction towards the sun.
+    pub sun_direction: Vector3<f32>,
+    pub sun_color: [f32; 3],
+    pub ambient: [f32; 3],
+    /// Intensity of the specular term, only applied on oceans.
+    /// 0 disables it.
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting {
+            sun_direction: Vector3::new(-0.6, 0.5, 0.6),
+            sun_color: [1.0, 0.95, 0.85],
+            ambient: [0.12, 0.12, 0.15],
+            specular: 0.6,
+            shininess: 32.0,
+        }
+    }
+}
+
+impl Lighting {
+    pub fn gpu_sun_direction(&self) -> [f32; 4] {
+        gpu::direction4(&self.sun_direction.normalize())
+    }
+
+    /// Sun color, with the specular intensity in `w`.
+    pub fn gpu_sun_color(&self) -> [f32; 4] {
+        let c = self.sun_color;
+        gpu::vec4(&Vector4::new(c[0], c[1], c[2], self.specular))
+    }
+
+    /// Ambient color, with the shininess in `w`.
+    pub fn gpu_ambient(&self) -> [f32; 4] {
+        let a = self.ambient;
+        gpu::vec4(&Vector4::new(a[0], a[1], a[2], self.shininess))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+    use super::*;
+
+    #[test]
+    fn packing() {
+        let lighting = Lighting {
+            sun_direction: Vector3::new(0., 2., 0.),
+            sun_color: [1., 0.5, 0.25],
+            ambient: [0.1, 0.2, 0.3],
+            specular: 0.75,
+            shininess: 16.,
+        };
+        assert_eq!(lighting.gpu_sun_direction(), [0., 1., 0., 0.]);
+        assert_eq!(lighting.gpu_sun_color(), [1., 0.5, 0.25, 0.75]);
+        assert_eq!(lighting.gpu_ambient(), [0.1, 0.2, 0.3, 16.]);
+    }
+
+    #[test]
+    fn default_sun_is_normalized() {
+        let d = Lighting::default().gpu_sun_direction();
+        assert!((Vector3::new(d[0], d[1], d[2]).norm() - 1.).abs() < 1e-6);
+        assert_eq!(d[3], 0.);
+    }
+}
@@ -0,0 +1,5 @@
+extern crate primus_polygoni;
+
+fn main() {
+    primus_polygoni::run();
+}
@@ -0,0 +1,9 @@
+mod validate;
+mod optimize;
+
+pub use self::validate::{validate, Report};
+pub use self::optimize::{optimize, optimize_vertex_cache, optimize_vertex_fetch,
+                         simulate_vertex_cache, CacheStats};
+
+/// Index type of the generated index buffers.
+pub type Index = u16;
@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+use scene::Vertex;
+use super::Index;
+
+// Tom Forsyth, "Linear-Speed Vertex Cache Optimisation", 2006.
+// https://tomforsyth1000.github.io/papers/fast_vert_cache_opt.html
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+struct VertexData {
+    /// Position in the simulated LRU cache.
+    cache_position: Option<usize>,
+    /// Triangles not yet emitted that use this vertex.
+    triangles: Vec<usize>,
+    score: f32,
+}
+
+impl VertexData {
+    fn compute_score(&self) -> f32 {
+        if self.triangles.is_empty() {
+            return -1.0;
+        }
+
+        let mut score = match self.cache_position {
+            Some(p) if p < 3 => LAST_TRIANGLE_SCORE,
+            Some(p) => {
+                let scaler = 1.0 / (CACHE_SIZE - 3) as f32;
+                (1.0 - (p - 3) as f32 * scaler).powf(CACHE_DECAY_POWER)
+            }
+            None => 0.0,
+        };
+        score += VALENCE_BOOST_SCALE *
+            (self.triangles.len() as f32).powf(-VALENCE_BOOST_POWER);
+        score
+    }
+}
+
+/// Reorders triangles so that consecutive triangles share vertices,
+/// which makes better use of the post-transform vertex cache.
+pub fn optimize_vertex_cache(indices: &[Index], vertex_count: usize) -> Vec<Index> {
+    debug_assert!(indices.len() % 3 == 0);
+    let triangle_count = indices.len() / 3;
+
+    let mut vertices: Vec<VertexData> = (0..vertex_count).map(|_| VertexData {
+        cache_position: None,
+        triangles: Vec::new(),
+        score: 0.0,
+    }).collect();
+    for (t, tri) in indices.chunks(3).enumerate() {
+        for &i in tri {
+            vertices[i as usize].triangles.push(t);
+        }
+    }
+    for v in &mut vertices {
+        v.score = v.compute_score();
+    }
+
+    let triangle_score = |vertices: &[VertexData], t: usize| {
+        indices[t * 3..t * 3 + 3].iter()
+            .map(|&i| vertices[i as usize].score)
+            .sum::<f32>()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cache: Vec<Index> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best: Option<usize> = None;
+    // triangles before this one have all been emitted
+    let mut scan_start = 0;
+
+    for _ in 0..triangle_count {
+        let t = match best {
+            Some(t) => t,
+            None => {
+                // nothing useful in the cache, fall back to a full scan
+                let mut best_score = -1.0;
+                let mut best_t = None;
+                while scan_start < triangle_count && emitted[scan_start] {
+                    scan_start += 1;
+                }
+                for t in scan_start..triangle_count {
+                    if !emitted[t] {
+                        let score = triangle_score(&vertices, t);
+                        if score > best_score {
+                            best_score = score;
+                            best_t = Some(t);
+                        }
+                    }
+                }
+                best_t.expect("no triangle left to emit")
+            }
+        };
+
+        emitted[t] = true;
+        let tri = &indices[t * 3..t * 3 + 3];
+        output.extend_from_slice(tri);
+
+        for &i in tri {
+            let v = &mut vertices[i as usize];
+            let p = v.triangles.iter().position(|&o| o == t).unwrap();
+            v.triangles.swap_remove(p);
+
+            if let Some(p) = cache.iter().position(|&c| c == i) {
+                cache.remove(p);
+            }
+        }
+        // most recently used vertices go first
+        for &i in tri.iter().rev() {
+            cache.insert(0, i);
+        }
+        for i in cache.drain(CACHE_SIZE.min(cache.len())..) {
+            vertices[i as usize].cache_position = None;
+            vertices[i as usize].score = vertices[i as usize].compute_score();
+        }
+        for (p, &i) in cache.iter().enumerate() {
+            let v = &mut vertices[i as usize];
+            v.cache_position = Some(p);
+            v.score = v.compute_score();
+        }
+
+        best = None;
+        let mut best_score = -1.0;
+        for &i in &cache {
+            for &o in &vertices[i as usize].triangles {
+                let score = triangle_score(&vertices, o);
+                if score > best_score {
+                    best_score = score;
+                    best = Some(o);
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/// Reorders vertices by first use in the index buffer,
+/// so that vertex fetches walk the vertex buffer linearly.
+/// Unreferenced vertices are dropped.
+pub fn optimize_vertex_fetch(vertices: &[Vertex], indices: &[Index])
+                             -> (Vec<Vertex>, Vec<Index>)
+{
+    let mut remap: Vec<Option<Index>> = vec![None; vertices.len()];
+    let mut new_vertices = Vec::with_capacity(vertices.len());
+
+    let new_indices = indices.iter().map(|&i| {
+        match remap[i as usize] {
+            Some(n) => n,
+            None => {
+                let n = new_vertices.len() as Index;
+                new_vertices.push(vertices[i as usize]);
+                remap[i as usize] = Some(n);
+                n
+            }
+        }
+    }).collect();
+
+    (new_vertices, new_indices)
+}
+
+/// Both vertex cache and vertex fetch optimisations.
+pub fn optimize(vertices: &[Vertex], indices: &[Index]) -> (Vec<Vertex>, Vec<Index>) {
+    let indices = optimize_vertex_cache(indices, vertices.len());
+    optimize_vertex_fetch(vertices, &indices)
+}
+
+/// Simulated post-transform cache statistics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub misses: usize,
+    /// Average cache miss ratio: transformed vertices per triangle.
+    /// 0.5 is the theoretical optimum for large regular meshes, 3 the worst.
+    pub acmr: f32,
+    /// Average transform to vertex ratio: transformed vertices per vertex.
+    /// 1 is optimal.
+    pub atvr: f32,
+}
+
+/// Simulates a FIFO post-transform vertex cache of `cache_size` entries,
+/// which is how most hardware behaves.
+pub fn simulate_vertex_cache(indices: &[Index],
+                             vertex_count: usize,
+                             cache_size: usize) -> CacheStats {
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+
+    for &i in indices {
+        if !cache.contains(&i) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(i);
+        }
+    }
+
+    CacheStats {
+        misses: misses,
+        acmr: misses as f32 / (indices.len() / 3) as f32,
+        atvr: misses as f32 / vertex_count as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use icosphere;
+    use mesh::Index;
+    use super::*;
+
+    /// Triangles starting with their smallest index, winding kept, sorted.
+    fn triangle_set(indices: &[Index]) -> Vec<[Index; 3]> {
+        let mut triangles: Vec<_> = indices.chunks(3).map(|t| {
+            if t[0] <= t[1] && t[0] <= t[2] {
+                [t[0], t[1], t[2]]
+            } else if t[1] <= t[2] {
+                [t[1], t[2], t[0]]
+            } else {
+                [t[2], t[0], t[1]]
+            }
+        }).collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn vertex_cache_keeps_triangles() {
+        let (vertices, indices) = icosphere::generate(3);
+        let optimized = optimize_vertex_cache(&indices, vertices.len());
+        assert_eq!(triangle_set(&optimized), triangle_set(&indices));
+    }
+
+    #[test]
+    fn vertex_fetch_keeps_triangles() {
+        let (vertices, indices) = icosphere::generate(3);
+        let indices = optimize_vertex_cache(&indices, vertices.len());
+        let (new_vertices, new_indices) = optimize_vertex_fetch(&vertices, &indices);
+
+        assert_eq!(new_vertices.len(), vertices.len());
+        assert_eq!(new_indices.len(), indices.len());
+        for (&i, &n) in indices.iter().zip(&new_indices) {
+            assert_eq!(new_vertices[n as usize].pos, vertices[i as usize].pos);
+        }
+        // first uses come in order
+        let mut next = 0;
+        for &n in &new_indices {
+            assert!(n <= next);
+            if n == next { next += 1; }
+        }
+    }
+
+    #[test]
+    fn fewer_cache_misses() {
+        for &cache_size in &[16, 32] {
+            let (vertices, indices) = icosphere::generate(4);
+            let before = simulate_vertex_cache(&indices, vertices.len(), cache_size);
+            let (vertices, indices) = optimize(&vertices, &indices);
+            let after = simulate_vertex_cache(&indices, vertices.len(), cache_size);
+
+            assert!(after.acmr < before.acmr, "{:?} then {:?}", before, after);
+            assert!(after.atvr < before.atvr, "{:?} then {:?}", before, after);
+            assert!(after.acmr < 1., "{:?}", after);
+            assert!(after.atvr >= 1.);
+        }
+    }
+
+    #[test]
+    fn simulated_cache() {
+        // two triangles sharing an edge
+        let stats = simulate_vertex_cache(&[0, 1, 2, 2, 1, 3], 4, 16);
+        assert_eq!(stats.misses, 4);
+        assert_eq!(stats.acmr, 2.);
+        assert_eq!(stats.atvr, 1.);
+
+        // a cache of 3 forgets vertex 0 before it comes back
+        let stats = simulate_vertex_cache(&[0, 1, 2, 2, 1, 3, 3, 1, 0], 4, 3);
+        assert_eq!(stats.misses, 5);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use scene::Vertex;
+use super::Index;
+
+/// Result of `validate`, listing everything suspicious about a mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    pub edge_count: usize,
+    /// Triangles referencing a vertex that does not exist.
+    pub invalid_triangles: Vec<usize>,
+    /// Triangles with repeated indices or a (near) zero area.
+    pub degenerate_triangles: Vec<usize>,
+    /// Triangles whose normal points towards the mesh centroid.
+    pub inward_triangles: Vec<usize>,
+    /// Edges used by a single triangle.
+    pub boundary_edges: usize,
+    /// Edges shared by more than two triangles.
+    pub non_manifold_edges: usize,
+    /// Edges traversed twice in the same direction,
+    /// meaning that the two adjacent triangles disagree on winding.
+    pub inconsistent_edges: usize,
+    /// Pairs of distinct vertices sharing the same position.
+    pub duplicate_vertices: Vec<(Index, Index)>,
+    /// Vertices not referenced by any triangle.
+    pub unreferenced_vertices: usize,
+    /// V - E + F, computed over the referenced vertices.
+    pub euler_characteristic: isize,
+    /// Vertices with texture coordinates outside of [0, 1].
+    pub uv_out_of_range: Vec<Index>,
+}
+
+impl Report {
+    /// No defect was found.
+    pub fn is_ok(&self) -> bool {
+        self.invalid_triangles.is_empty() &&
+        self.degenerate_triangles.is_empty() &&
+        self.inward_triangles.is_empty() &&
+        self.non_manifold_edges == 0 &&
+        self.inconsistent_edges == 0 &&
+        self.duplicate_vertices.is_empty() &&
+        self.unreferenced_vertices == 0 &&
+        self.uv_out_of_range.is_empty()
+    }
+
+    /// No defect was found and the mesh is a closed surface
+    /// topologically equivalent to a sphere.
+    pub fn is_closed_sphere(&self) -> bool {
+        self.is_ok() && self.boundary_edges == 0 && self.euler_characteristic == 2
+    }
+}
+
+const AREA_EPSILON: f32 = 1e-12;
+const POSITION_EPSILON: f32 = 1e-5;
+
+pub fn validate(vertices: &[Vertex], indices: &[Index]) -> Report {
+    debug_assert!(indices.len() % 3 == 0);
+
+    let mut report = Report {
+        vertex_count: vertices.len(),
+        triangle_count: indices.len() / 3,
+        edge_count: 0,
+        invalid_triangles: Vec::new(),
+        degenerate_triangles: Vec::new(),
+        inward_triangles: Vec::new(),
+        boundary_edges: 0,
+        non_manifold_edges: 0,
+        inconsistent_edges: 0,
+        duplicate_vertices: Vec::new(),
+        unreferenced_vertices: 0,
+        euler_characteristic: 0,
+        uv_out_of_range: Vec::new(),
+    };
+
+    let position = |i: Index| {
+        let p = vertices[i as usize].pos;
+        [p[0], p[1], p[2]]
+    };
+
+    let mut referenced = vec![false; vertices.len()];
+    let mut centroid = [0.0; 3];
+    for &i in indices {
+        if let Some(r) = referenced.get_mut(i as usize) {
+            if !*r {
+                *r = true;
+                let p = position(i);
+                centroid = add(centroid, p);
+            }
+        }
+    }
+    let referenced_count = referenced.iter().filter(|&&r| r).count();
+    report.unreferenced_vertices = vertices.len() - referenced_count;
+    if referenced_count > 0 {
+        centroid = scale(centroid, 1.0 / referenced_count as f32);
+    }
+
+    // directed edge -> number of uses
+    let mut directed: HashMap<(Index, Index), usize> = HashMap::new();
+    // undirected edge -> number of adjacent triangles
+    let mut undirected: HashMap<(Index, Index), usize> = HashMap::new();
+
+    for (t, tri) in indices.chunks(3).enumerate() {
+        if tri.iter().any(|&i| i as usize >= vertices.len()) {
+            report.invalid_triangles.push(t);
+            continue;
+        }
+
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            *directed.entry((a, b)).or_insert(0) += 1;
+            let key = if a < b { (a, b) } else { (b, a) };
+            *undirected.entry(key).or_insert(0) += 1;
+        }
+
+        let (pa, pb, pc) = (position(tri[0]), position(tri[1]), position(tri[2]));
+        let normal = cross(sub(pb, pa), sub(pc, pa));
+        if tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0] ||
+           dot(normal, normal) <= AREA_EPSILON {
+            report.degenerate_triangles.push(t);
+            continue;
+        }
+
+        let center = scale(add(add(pa, pb), pc), 1.0 / 3.0);
+        if dot(normal, sub(center, centroid)) < 0.0 {
+            report.inward_triangles.push(t);
+        }
+    }
+
+    report.edge_count = undirected.len();
+    for &count in undirected.values() {
+        if count == 1 { report.boundary_edges += 1; }
+        if count > 2 { report.non_manifold_edges += 1; }
+    }
+    report.inconsistent_edges = directed.values().filter(|&&c| c > 1).count();
+
+    let mut positions: HashMap<[i32; 3], Index> = HashMap::new();
+    for (i, v) in vertices.iter().enumerate() {
+        let i = i as Index;
+        let key = [
+            (v.pos[0] / POSITION_EPSILON).round() as i32,
+            (v.pos[1] / POSITION_EPSILON).round() as i32,
+            (v.pos[2] / POSITION_EPSILON).round() as i32,
+        ];
+        let first = positions.get(&key).cloned();
+        match first {
+            Some(first) => report.duplicate_vertices.push((first, i)),
+            None => { positions.insert(key, i); }
+        }
+
+        let uv = v.tex_coord;
+        if uv[0] < 0.0 || uv[0] > 1.0 || uv[1] < 0.0 || uv[1] > 1.0 {
+            report.uv_out_of_range.push(i);
+        }
+    }
+
+    report.euler_characteristic = referenced_count as isize
+        - report.edge_count as isize
+        + (report.triangle_count - report.invalid_triangles.len()) as isize;
+
+    report
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1],
+     a[2] * b[0] - a[0] * b[2],
+     a[0] * b[1] - a[1] * b[0]]
+}
@@ -0,0 +1,629 @@
+//! Backend that executes nothing and records what it was asked to do,
+//! so that scenes and upload strategies run without a GPU.
+//!
+//! Buffers and textures are only identifiers. Upload buffers get memory
+//! to map, everything else only keeps its creation info.
+
+use std::{mem, slice};
+use gfx_core::{self, buffer, command, format, handle, mapping, pso, shade, state, target, texture};
+use gfx_core::{Capabilities, IndexType, VertexCount, SubmissionResult};
+use gfx_core::factory::{ResourceViewError, TargetViewError};
+use gfx_core::handle::Producer;
+use gfx_core::memory::Usage;
+
+/// Identifies a resource, in creation order.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Id(pub usize);
+
+/// Signaled as soon as it is created, since nothing is executed.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Fence(pub usize);
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Resources {}
+
+impl gfx_core::Resources for Resources {
+    type Buffer = Id;
+    type Shader = Id;
+    type Program = Id;
+    type PipelineStateObject = Id;
+    type Texture = Id;
+    type ShaderResourceView = Id;
+    type UnorderedAccessView = Id;
+    type RenderTargetView = Id;
+    type DepthStencilView = Id;
+    type Sampler = Id;
+    type Fence = Fence;
+    type Mapping = Mapping;
+}
+
+/// Memory of an upload or download buffer, freed with it.
+///
+/// Held as `u64` words, so that it is aligned for any texel or vertex type.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct Mapping {
+    address: usize,
+    words: usize,
+}
+
+impl Mapping {
+    fn new(size: usize) -> Self {
+        let words = (size + mem::size_of::<u64>() - 1) / mem::size_of::<u64>();
+        let mut memory = vec![0u64; words].into_boxed_slice();
+        let address = memory.as_mut_ptr() as usize;
+        mem::forget(memory);
+        Mapping { address: address, words: words }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            let memory = slice::from_raw_parts_mut(self.address as *mut u64, self.words);
+            drop(Box::from_raw(memory));
+        }
+    }
+}
+
+impl mapping::Gate<Resources> for Mapping {
+    unsafe fn set<T>(&self, index: usize, val: T) {
+        *(self.address as *mut T).offset(index as isize) = val;
+    }
+
+    unsafe fn slice<'a, 'b, T>(&'a self, len: usize) -> &'b [T] {
+        slice::from_raw_parts(self.address as *const T, len)
+    }
+
+    unsafe fn mut_slice<'a, 'b, T>(&'a self, len: usize) -> &'b mut [T] {
+        slice::from_raw_parts_mut(self.address as *mut T, len)
+    }
+}
+
+/// Data sent to a buffer or a texture by a command buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Upload {
+    /// `size` bytes from memory at `offset` bytes into `buffer`.
+    Buffer { buffer: Id, offset: usize, size: usize },
+    /// `size` bytes from memory to the `info` region of `texture`.
+    Texture { texture: Id, info: texture::RawImageInfo, size: usize },
+    /// `size` bytes from `src` at `src_offset` to `dst` at `dst_offset`.
+    BufferToBuffer { src: Id, dst: Id, src_offset: usize, dst_offset: usize, size: usize },
+    /// From `buffer` at `offset` bytes to the `info` region of `texture`.
+    BufferToTexture { buffer: Id, offset: usize, texture: Id, info: texture::RawImageInfo },
+}
+
+impl Upload {
+    /// Bytes transferred.
+    pub fn size(&self) -> usize {
+        match *self {
+            Upload::Buffer { size, .. } |
+            Upload::Texture { size, .. } |
+            Upload::BufferToBuffer { size, .. } => size,
+            Upload::BufferToTexture { info, .. } =>
+                info.get_texel_count() * (info.format.0.get_total_bits() as usize / 8),
+        }
+    }
+
+    /// Texture written, if any.
+    pub fn texture(&self) -> Option<Id> {
+        match *self {
+            Upload::Texture { texture, .. } |
+            Upload::BufferToTexture { texture, .. } => Some(texture),
+            _ => None,
+        }
+    }
+}
+
+/// A draw call, with the pipeline bound when it was recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Draw {
+    pub pipeline: Option<Id>,
+    pub vertices: VertexCount,
+    /// 1 unless instanced.
+    pub instances: u32,
+    pub indexed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct CommandBuffer {
+    uploads: Vec<Upload>,
+    draws: Vec<Draw>,
+    pipeline: Option<Id>,
+}
+
+impl command::Buffer<Resources> for CommandBuffer {
+    fn reset(&mut self) {
+        self.uploads.clear();
+        self.draws.clear();
+        self.pipeline = None;
+    }
+
+    fn bind_pipeline_state(&mut self, pso: Id) {
+        self.pipeline = Some(pso);
+    }
+
+    fn bind_vertex_buffers(&mut self, _: pso::VertexBufferSet<Resources>) {}
+    fn bind_constant_buffers(&mut self, _: &[pso::ConstantBufferParam<Resources>]) {}
+    fn bind_global_constant(&mut self, _: shade::Location, _: shade::UniformValue) {}
+    fn bind_resource_views(&mut self, _: &[pso::ResourceViewParam<Resources>]) {}
+    fn bind_unordered_views(&mut self, _: &[pso::UnorderedViewParam<Resources>]) {}
+    fn bind_samplers(&mut self, _: &[pso::SamplerParam<Resources>]) {}
+    fn bind_pixel_targets(&mut self, _: pso::PixelTargetSet<Resources>) {}
+    fn bind_index(&mut self, _: Id, _: IndexType) {}
+    fn set_scissor(&mut self, _: target::Rect) {}
+    fn set_ref_values(&mut self, _: state::RefValues) {}
+
+    fn copy_buffer(&mut self, src: Id, dst: Id,
+                   src_offset_bytes: usize, dst_offset_bytes: usize,
+                   size_bytes: usize) {
+        self.uploads.push(Upload::BufferToBuffer {
+            src: src,
+            dst: dst,
+            src_offset: src_offset_bytes,
+            dst_offset: dst_offset_bytes,
+            size: size_bytes,
+        });
+    }
+
+    fn copy_buffer_to_texture(&mut self, src: Id, src_offset_bytes: usize,
+                              dst: Id, _: texture::Kind, _: Option<texture::CubeFace>,
+                              info: texture::RawImageInfo) {
+        self.uploads.push(Upload::BufferToTexture {
+            buffer: src,
+            offset: src_offset_bytes,
+            texture: dst,
+            info: info,
+        });
+    }
+
+    fn copy_texture_to_buffer(&mut self, _: Id, _: texture::Kind, _: Option<texture::CubeFace>,
+                              _: texture::RawImageInfo, _: Id, _: usize) {}
+
+    fn update_buffer(&mut self, buffer: Id, data: &[u8], offset: usize) {
+        self.uploads.push(Upload::Buffer { buffer: buffer, offset: offset, size: data.len() });
+    }
+
+    fn update_texture(&mut self, texture: Id, _: texture::Kind, _: Option<texture::CubeFace>,
+                      data: &[u8], info: texture::RawImageInfo) {
+        self.uploads.push(Upload::Texture { texture: texture, info: info, size: data.len() });
+    }
+
+    fn generate_mipmap(&mut self, _: Id) {}
+    fn clear_color(&mut self, _: Id, _: command::ClearColor) {}
+    fn clear_depth_stencil(&mut self, _: Id, _: Option<target::Depth>,
+                           _: Option<target::Stencil>) {}
+
+    fn call_draw(&mut self, _: VertexCount, count: VertexCount,
+                 instances: Option<command::InstanceParams>) {
+        self.draws.push(Draw {
+            pipeline: self.pipeline,
+            vertices: count,
+            instances: instances.map(|(count, _)| count).unwrap_or(1),
+            indexed: false,
+        });
+    }
+
+    fn call_draw_indexed(&mut self, _: VertexCount, count: VertexCount, _: VertexCount,
+                         instances: Option<command::InstanceParams>) {
+        self.draws.push(Draw {
+            pipeline: self.pipeline,
+            vertices: count,
+            instances: instances.map(|(count, _)| count).unwrap_or(1),
+            indexed: true,
+        });
+    }
+}
+
+fn capabilities() -> Capabilities {
+    Capabilities {
+        max_vertex_count: !0,
+        max_index_count: !0,
+        max_texture_size: 1 << 14,
+        max_patch_size: 32,
+        instance_base_supported: true,
+        instance_call_supported: true,
+        instance_rate_supported: true,
+        vertex_base_supported: true,
+        srgb_color_supported: true,
+        constant_buffer_supported: true,
+        unordered_access_view_supported: false,
+        separate_blending_slots_supported: true,
+        copy_buffer_supported: true,
+    }
+}
+
+/// Everything submitted so far, in order.
+pub struct Device {
+    capabilities: Capabilities,
+    handles: handle::Manager<Resources>,
+    pub uploads: Vec<Upload>,
+    pub draws: Vec<Draw>,
+    pub submissions: usize,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        Device {
+            capabilities: capabilities(),
+            handles: handle::Manager::new(),
+            uploads: Vec::new(),
+            draws: Vec::new(),
+            submissions: 0,
+        }
+    }
+
+    /// Forgets what was submitted.
+    pub fn clear(&mut self) {
+        self.uploads.clear();
+        self.draws.clear();
+        self.submissions = 0;
+    }
+
+    /// Bytes uploaded to `texture`.
+    pub fn texture_upload_size(&self, texture: Id) -> usize {
+        self.uploads.iter()
+            .filter(|upload| upload.texture() == Some(texture))
+            .map(|upload| upload.size())
+            .sum()
+    }
+}
+
+impl gfx_core::Device for Device {
+    type Resources = Resources;
+    type CommandBuffer = CommandBuffer;
+
+    fn get_capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    fn pin_submitted_resources(&mut self, handles: &handle::Manager<Resources>) {
+        self.handles.extend(handles);
+    }
+
+    fn submit(&mut self,
+              cb: &mut CommandBuffer,
+              _: &command::AccessInfo<Resources>)
+              -> SubmissionResult<()> {
+        self.uploads.extend(cb.uploads.drain(..));
+        self.draws.extend(cb.draws.drain(..));
+        self.submissions += 1;
+        Ok(())
+    }
+
+    fn fenced_submit(&mut self,
+                     cb: &mut CommandBuffer,
+                     access: &command::AccessInfo<Resources>,
+                     _after: Option<handle::Fence<Resources>>)
+                     -> SubmissionResult<handle::Fence<Resources>> {
+        try!(gfx_core::Device::submit(self, cb, access));
+        Ok(self.handles.make_fence(Fence(self.submissions)))
+    }
+
+    fn wait_fence(&mut self, _: &handle::Fence<Resources>) {}
+
+    fn cleanup(&mut self) {
+        self.handles.clear();
+    }
+}
+
+/// Creates identifiers, and records what was created.
+pub struct Factory {
+    capabilities: Capabilities,
+    handles: handle::Manager<Resources>,
+    next_id: usize,
+    pub buffers: Vec<buffer::Info>,
+    pub textures: Vec<texture::Info>,
+}
+
+impl Factory {
+    pub fn new() -> Self {
+        Factory {
+            capabilities: capabilities(),
+            handles: handle::Manager::new(),
+            next_id: 0,
+            buffers: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn create_command_buffer(&mut self) -> CommandBuffer {
+        CommandBuffer::default()
+    }
+
+    fn id(&mut self) -> Id {
+        self.next_id += 1;
+        Id(self.next_id - 1)
+    }
+
+    fn buffer(&mut self, info: buffer::Info) -> handle::RawBuffer<Resources> {
+        let mapping = match info.usage {
+            Usage::Upload | Usage::Download => Some(Mapping::new(info.size)),
+            _ => None,
+        };
+        let id = self.id();
+        self.buffers.push(info.clone());
+        self.handles.make_buffer(id, info, mapping)
+    }
+}
+
+/// A program without any reflected variable: pipelines link to it
+/// without binding anything, which is all the recording needs.
+fn empty_program_info() -> shade::ProgramInfo {
+    shade::ProgramInfo {
+        vertex_attributes: Vec::new(),
+        globals: Vec::new(),
+        constant_buffers: Vec::new(),
+        textures: Vec::new(),
+        unordereds: Vec::new(),
+        samplers: Vec::new(),
+        outputs: Vec::new(),
+        output_depth: false,
+        knows_outputs: false,
+    }
+}
+
+impl gfx_core::Factory<Resources> for Factory {
+    fn get_capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    fn create_buffer_raw(&mut self, info: buffer::Info)
+                         -> Result<handle::RawBuffer<Resources>, buffer::CreationError> {
+        Ok(self.buffer(info))
+    }
+
+    fn create_buffer_immutable_raw(&mut self, data: &[u8], stride: usize,
+                                   role: buffer::Role, bind: gfx_core::memory::Bind)
+                                   -> Result<handle::RawBuffer<Resources>, buffer::CreationError> {
+        Ok(self.buffer(buffer::Info {
+            role: role,
+            usage: Usage::Data,
+            bind: bind,
+            size: data.len(),
+            stride: stride,
+        }))
+    }
+
+    fn create_pipeline_state_raw(&mut self, program: &handle::Program<Resources>,
+                                 _: &pso::Descriptor)
+                                 -> Result<handle::RawPipelineState<Resources>, pso::CreationError> {
+        let id = self.id();
+        Ok(self.handles.make_pso(id, program))
+    }
+
+    fn create_program(&mut self, _: &gfx_core::ShaderSet<Resources>)
+                      -> Result<handle::Program<Resources>, shade::CreateProgramError> {
+        let id = self.id();
+        Ok(self.handles.make_program(id, empty_program_info()))
+    }
+
+    fn create_shader(&mut self, _: shade::Stage, _: &[u8])
+                     -> Result<handle::Shader<Resources>, shade::CreateShaderError> {
+        let id = self.id();
+        Ok(self.handles.make_shader(id))
+    }
+
+    fn create_sampler(&mut self, info: texture::SamplerInfo) -> handle::Sampler<Resources> {
+        let id = self.id();
+        self.handles.make_sampler(id, info)
+    }
+
+    fn read_mapping<'a, 'b, T>(&'a mut self, buf: &'b handle::Buffer<Resources, T>)
+                               -> Result<mapping::Reader<'b, Resources, T>, mapping::Error>
+        where T: Copy
+    {
+        unsafe { mapping::read(buf.raw(), |_| {}) }
+    }
+
+    fn write_mapping<'a, 'b, T>(&'a mut self, buf: &'b handle::Buffer<Resources, T>)
+                                -> Result<mapping::Writer<'b, Resources, T>, mapping::Error>
+        where T: Copy
+    {
+        unsafe { mapping::write(buf.raw(), |_| {}) }
+    }
+
+    fn create_texture_raw(&mut self, info: texture::Info, _: Option<format::ChannelType>,
+                          _: Option<&[&[u8]]>)
+                          -> Result<handle::RawTexture<Resources>, texture::CreationError> {
+        let id = self.id();
+        self.textures.push(info);
+        Ok(self.handles.make_texture(id, info))
+    }
+
+    fn view_buffer_as_shader_resource_raw(&mut self, buf: &handle::RawBuffer<Resources>,
+                                          _: format::Format)
+                                          -> Result<handle::RawShaderResourceView<Resources>,
+                                                    ResourceViewError> {
+        let id = self.id();
+        Ok(self.handles.make_buffer_srv(id, buf))
+    }
+
+    fn view_buffer_as_unordered_access_raw(&mut self, buf: &handle::RawBuffer<Resources>)
+                                           -> Result<handle::RawUnorderedAccessView<Resources>,
+                                                     ResourceViewError> {
+        let id = self.id();
+        Ok(self.handles.make_buffer_uav(id, buf))
+    }
+
+    fn view_texture_as_shader_resource_raw(&mut self, tex: &handle::RawTexture<Resources>,
+                                           _: texture::ResourceDesc)
+                                           -> Result<handle::RawShaderResourceView<Resources>,
+                                                     ResourceViewError> {
+        let id = self.id();
+        Ok(self.handles.make_texture_srv(id, tex))
+    }
+
+    fn view_texture_as_unordered_access_raw(&mut self, tex: &handle::RawTexture<Resources>)
+                                            -> Result<handle::RawUnorderedAccessView<Resources>,
+                                                      ResourceViewError> {
+        let id = self.id();
+        Ok(self.handles.make_texture_uav(id, tex))
+    }
+
+    fn view_texture_as_render_target_raw(&mut self, tex: &handle::RawTexture<Resources>,
+                                         _: texture::RenderDesc)
+                                         -> Result<handle::RawRenderTargetView<Resources>,
+                                                   TargetViewError> {
+        let id = self.id();
+        let dimensions = tex.get_info().kind.get_dimensions();
+        Ok(self.handles.make_rtv(id, tex, dimensions))
+    }
+
+    fn view_texture_as_depth_stencil_raw(&mut self, tex: &handle::RawTexture<Resources>,
+                                         _: texture::DepthStencilDesc)
+                                         -> Result<handle::RawDepthStencilView<Resources>,
+                                                   TargetViewError> {
+        let id = self.id();
+        let dimensions = tex.get_info().kind.get_dimensions();
+        Ok(self.handles.make_dsv(id, tex, dimensions))
+    }
+}
+
+/// A device and a factory sharing nothing, like the OpenGL ones.
+pub fn create() -> (Device, Factory) {
+    (Device::new(), Factory::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use gfx;
+    use gfx::traits::FactoryExt;
+    use gfx_core::Factory as CoreFactory;
+    use gfx_core::memory::Typed;
+    use layout;
+    use scene::{Scene, ColorFormat, DepthFormat};
+    use strategy::{self, Strategy};
+    use super::*;
+
+    const SPHERE_COUNT: usize = 6;
+    const TEXTURE_SIZE: usize = 8;
+    /// Small enough to spread the layers over two arrays.
+    const MAX_LAYERS: usize = 4;
+
+    type TestScene = Scene<Resources, Strategy<Resources>>;
+
+    fn scene(name: &str, sky: bool, device: &mut Device, factory: &mut Factory,
+             encoder: &mut gfx::Encoder<Resources, CommandBuffer>) -> TestScene {
+        let (_, _, color_target) = factory.create_render_target::<ColorFormat>(64, 32).unwrap();
+        let (_, _, depth_target) = factory.create_depth_stencil::<DepthFormat>(64, 32).unwrap();
+        let layout = layout::from_name("ring").unwrap();
+        let scene = Scene::new(Strategy::from_name(name).unwrap(),
+                               SPHERE_COUNT,
+                               TEXTURE_SIZE,
+                               SPHERE_COUNT,
+                               MAX_LAYERS,
+                               &*layout,
+                               sky,
+                               color_target,
+                               depth_target,
+                               factory,
+                               encoder);
+        encoder.flush(device);
+        scene
+    }
+
+    #[test]
+    fn aligned_mapping() {
+        for size in 0..20 {
+            let mapping = Mapping::new(size);
+            assert_eq!(mapping.address % mem::align_of::<u64>(), 0);
+        }
+
+        let (_, mut factory) = create();
+        let buffer = factory.create_upload_buffer::<[f32; 4]>(3).unwrap();
+        {
+            let mut writer = factory.write_mapping(&buffer).unwrap();
+            writer[2] = [1., 2., 3., 4.];
+        }
+        let reader = factory.read_mapping(&buffer).unwrap();
+        assert_eq!(reader[0], [0.; 4]);
+        assert_eq!(reader[2], [1., 2., 3., 4.]);
+    }
+
+    #[test]
+    fn every_layer_uploaded() {
+        let texture_bytes = TEXTURE_SIZE * 2 * TEXTURE_SIZE * 4;
+        for &name in strategy::NAMES {
+            for &sky in &[false, true] {
+                let (mut device, mut factory) = create();
+                let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+                let mut scene = scene(name, sky, &mut device, &mut factory, &mut encoder);
+                assert_eq!(scene.arrays.len(), 2);
+                assert_eq!(scene.skybox.is_some(), sky);
+                // the instances, but no texture yet
+                assert!(device.uploads.iter().all(|upload| upload.texture().is_none()));
+                assert!(device.draws.is_empty());
+                device.clear();
+
+                let layers = scene.generate_textures(&mut encoder, &mut factory, &mut device);
+                assert_eq!(layers, SPHERE_COUNT + 1 + sky as usize, "{}", name);
+                let fence = encoder.fenced_flush_no_reset(&mut device, None).unwrap();
+                encoder.reset();
+                scene.implementation.submitted(fence);
+
+                for array in &scene.arrays {
+                    let uploaded = device.texture_upload_size(*array.texture.raw().resource());
+                    let expected = if name == "null" { 0 } else { array.layers() * texture_bytes };
+                    assert_eq!(uploaded, expected, "{} with sky {}", name, sky);
+                }
+                // the upload strategies send nothing else
+                assert!(device.uploads.iter().all(|upload| upload.texture().is_some()));
+                assert!(device.draws.is_empty());
+                let submissions = if name == "interleaved_submit" { layers + 1 } else { 1 };
+                assert_eq!(device.submissions, submissions, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn render() {
+        for &sky in &[false, true] {
+            let (mut device, mut factory) = create();
+            let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+            let mut scene = scene("null", sky, &mut device, &mut factory, &mut encoder);
+            scene.generate_textures(&mut encoder, &mut factory, &mut device);
+            encoder.flush(&mut device);
+            device.clear();
+
+            scene.render(2., &mut encoder);
+            encoder.flush(&mut device);
+            assert_eq!(scene.drawn_count(), SPHERE_COUNT);
+
+            // the sky and its stars come first, not instanced
+            let sky_draws = if sky { 2 } else { 0 };
+            for draw in &device.draws[..sky_draws] {
+                assert!(!draw.indexed);
+                assert_eq!(draw.instances, 1);
+            }
+            if sky {
+                assert_eq!(device.draws[0].vertices, 3);
+                assert_ne!(device.draws[0].pipeline, device.draws[1].pipeline);
+            }
+
+            // then one draw per array for the planets, and again for the atmospheres
+            let spheres = &device.draws[sky_draws..];
+            assert_eq!(spheres.len(), 2 * scene.arrays.len());
+            assert!(spheres.iter().all(|draw| draw.indexed));
+            let (planets, atmospheres) = spheres.split_at(scene.arrays.len());
+            for (planet, atmosphere) in planets.iter().zip(atmospheres) {
+                assert_eq!(planet.vertices, atmosphere.vertices);
+                assert_eq!(planet.instances, atmosphere.instances);
+                assert!(planet.pipeline.is_some());
+                assert_ne!(planet.pipeline, atmosphere.pipeline);
+            }
+            let instances: u32 = planets.iter().map(|draw| draw.instances).sum();
+            assert_eq!(instances as usize, SPHERE_COUNT);
+
+            // the skybox and scene constants, nothing to the textures
+            assert!(!device.uploads.is_empty());
+            assert!(device.uploads.iter().all(|upload| upload.texture().is_none()));
+
+            scene.atmosphere_pass = false;
+            device.clear();
+            scene.render(2., &mut encoder);
+            encoder.flush(&mut device);
+            assert_eq!(device.draws.len(), sky_draws + scene.arrays.len());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::f32::consts::PI;
+use nalgebra::{Point3, Vector2, Vector3};
+use scene::Sphere;
+use Camera;
+
+/// Keeps the camera orbiting around a target sphere.
+pub struct OrbitController {
+    target: Point3<f32>,
+    goal_target: Point3<f32>,
+    distance: f32,
+    goal_distance: f32,
+    min_distance: f32,
+    yaw: f32,
+    pitch: f32,
+
+    /// Radians per unit of normalized mouse movement.
+    pub sensitivity: f32,
+    /// Relative distance change per scroll step.
+    pub zoom_speed: f32,
+    /// How fast transitions converge, in 1/s.
+    pub smoothing: f32,
+}
+
+impl OrbitController {
+    /// Starts orbiting `sphere` from the current camera point of view.
+    pub fn new(camera: &Camera, sphere: &Sphere) -> Self {
+        let offset = camera.position() - sphere.center;
+        let distance = offset.norm().max(Self::min_distance(sphere));
+        let offset = if offset.norm_squared() > 0. {
+            offset / offset.norm()
+        } else {
+            -camera.ahead()
+        };
+
+        OrbitController {
+            target: sphere.center,
+            goal_target: sphere.center,
+            distance: distance,
+            goal_distance: Self::default_distance(sphere),
+            min_distance: Self::min_distance(sphere),
+            yaw: offset.x.atan2(offset.z),
+            pitch: offset.y.max(-1.).min(1.).asin(),
+
+            sensitivity: 2.0 * PI,
+            zoom_speed: 0.1,
+            smoothing: 4.0,
+        }
+    }
+
+    fn min_distance(sphere: &Sphere) -> f32 {
+        sphere.radius * 1.1
+    }
+
+    fn default_distance(sphere: &Sphere) -> f32 {
+        sphere.radius * 4.0
+    }
+
+    /// Smoothly moves over to a new target.
+    pub fn set_target(&mut self, sphere: &Sphere) {
+        self.goal_target = sphere.center;
+        self.min_distance = Self::min_distance(sphere);
+        self.goal_distance = Self::default_distance(sphere);
+    }
+
+    /// Follows a target moving by itself, without smoothing.
+    pub fn follow(&mut self, sphere: &Sphere) {
+        let offset = sphere.center - self.goal_target;
+        self.target += offset;
+        self.goal_target = sphere.center;
+    }
+
+    /// Rotates around the target following a mouse drag,
+    /// in normalized screen coordinates.
+    pub fn drag(&mut self, delta: Vector2<f32>) {
+        let limit = 0.5 * PI - 0.01;
+        self.yaw -= delta.x * self.sensitivity;
+        self.pitch = (self.pitch - delta.y * self.sensitivity)
+            .max(-limit).min(limit);
+    }
+
+    /// Moves closer (positive `steps`) or further away.
+    pub fn zoom(&mut self, steps: f32) {
+        self.goal_distance = (self.goal_distance * (1. - self.zoom_speed).powf(steps))
+            .max(self.min_distance);
+    }
+
+    pub fn update(&mut self, camera: &mut Camera, delta: f32) {
+        let t = 1. - (-self.smoothing * delta).exp();
+        self.target = self.target + (self.goal_target - self.target) * t;
+        self.distance += (self.goal_distance - self.distance) * t;
+
+        let offset = Vector3::new(self.pitch.cos() * self.yaw.sin(),
+                                  self.pitch.sin(),
+                                  self.pitch.cos() * self.yaw.cos());
+        camera.set_position(self.target + offset * self.distance);
+        camera.look_at(&self.target);
+    }
+}
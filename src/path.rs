@@ -0,0 +1,361 @@
+use std::{io, fs};
+use std::io::prelude::*;
+use std::path::Path;
+use nalgebra::{Point3, Vector4, Quaternion, UnitQuaternion};
+use Camera;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    /// Seconds since the start of the path.
+    pub time: f32,
+    pub position: Point3<f32>,
+    pub orientation: UnitQuaternion<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+/// A keyframed camera trajectory.
+///
+/// Positions are interpolated linearly or with a Catmull-Rom spline,
+/// orientations are always slerped.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        CameraPath {
+            keyframes: Vec::new(),
+            interpolation: Interpolation::CatmullRom,
+        }
+    }
+
+    /// Adds a keyframe, which must not be older than the last one.
+    pub fn push(&mut self, keyframe: Keyframe) {
+        debug_assert!(self.keyframes.last().map(|k| k.time <= keyframe.time).unwrap_or(true));
+        self.keyframes.push(keyframe);
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.)
+    }
+
+    /// Camera position and orientation at `time`,
+    /// clamped to the extent of the path.
+    pub fn sample(&self, time: f32) -> Option<(Point3<f32>, UnitQuaternion<f32>)> {
+        let ks = &self.keyframes;
+        if ks.is_empty() {
+            return None;
+        }
+
+        let next = ks.iter().position(|k| k.time > time).unwrap_or(ks.len());
+        if next == 0 {
+            return Some((ks[0].position, ks[0].orientation));
+        }
+        if next == ks.len() {
+            let last = ks[ks.len() - 1];
+            return Some((last.position, last.orientation));
+        }
+
+        let (a, b) = (&ks[next - 1], &ks[next]);
+        let span = b.time - a.time;
+        let t = if span > 0. { (time - a.time) / span } else { 1. };
+
+        let position = match self.interpolation {
+            Interpolation::Linear => a.position + (b.position - a.position) * t,
+            Interpolation::CatmullRom => {
+                let before = if next >= 2 { ks[next - 2].position } else { a.position };
+                let after = ks.get(next + 1).map(|k| k.position).unwrap_or(b.position);
+                catmull_rom(&before, &a.position, &b.position, &after, t)
+            }
+        };
+
+        Some((position, slerp(&a.orientation, &b.orientation, t)))
+    }
+
+    /// Moves `camera` to where the path is at `time`.
+    /// Returns `false` once the path is over, except for a path whose
+    /// keyframes all have the same time, like a single keyframe:
+    /// it holds its last keyframe instead.
+    pub fn apply(&self, camera: &mut Camera, time: f32) -> bool {
+        if let Some((position, orientation)) = self.sample(time) {
+            camera.set_position(position);
+            camera.set_orientation(orientation);
+        }
+        let still = self.keyframes.first().map(|k| k.time) == Some(self.duration());
+        still || time <= self.duration()
+    }
+
+    /// Reads a path saved by `save`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut text = String::new();
+        try!(try!(fs::File::open(path)).read_to_string(&mut text));
+        CameraPath::parse(&text)
+    }
+
+    /// Reads keyframes written by `save`,
+    /// which must be in chronological order and at least one.
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut camera_path = CameraPath::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = try!(line.split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+            if values.len() != 8 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("expected 8 values, got: {}", line)));
+            }
+            if !(values[0] >= camera_path.duration()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("time going backwards: {}", line)));
+            }
+
+            camera_path.push(Keyframe {
+                time: values[0],
+                position: Point3::new(values[1], values[2], values[3]),
+                orientation: UnitQuaternion::new_normalize(
+                    Quaternion::new(values[4], values[5], values[6], values[7])),
+            });
+        }
+
+        if camera_path.keyframes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no keyframe"));
+        }
+        Ok(camera_path)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = io::BufWriter::new(try!(fs::File::create(path)));
+
+        try!(writeln!(w, "# time, position (x y z), orientation (w i j k)"));
+        for k in &self.keyframes {
+            let q = k.orientation.quaternion().coords;
+            try!(writeln!(w, "{} {} {} {} {} {} {} {}",
+                          k.time,
+                          k.position.x, k.position.y, k.position.z,
+                          q[3], q[0], q[1], q[2]));
+        }
+        Ok(())
+    }
+}
+
+/// Records the interactive camera into a `CameraPath`.
+pub struct PathRecorder {
+    path: CameraPath,
+    /// Minimum time between two keyframes, in seconds.
+    pub interval: f32,
+}
+
+impl PathRecorder {
+    pub fn new() -> Self {
+        PathRecorder {
+            path: CameraPath::new(),
+            interval: 1. / 30.,
+        }
+    }
+
+    /// Records the camera `time` seconds after the start of the recording.
+    pub fn record(&mut self, camera: &Camera, time: f32) {
+        let due = self.path.keyframes.last()
+            .map(|k| time - k.time >= self.interval)
+            .unwrap_or(true);
+
+        if due {
+            self.path.push(Keyframe {
+                time: time,
+                position: camera.position(),
+                orientation: camera.orientation(),
+            });
+        }
+    }
+
+    pub fn finish(self) -> CameraPath {
+        self.path
+    }
+}
+
+fn catmull_rom(p0: &Point3<f32>, p1: &Point3<f32>, p2: &Point3<f32>, p3: &Point3<f32>,
+               t: f32) -> Point3<f32> {
+    let (t2, t3) = (t * t, t * t * t);
+    let (v0, v1, v2, v3) = (p0.coords, p1.coords, p2.coords, p3.coords);
+    Point3::from_coordinates(
+        (v1 * 2. +
+         (v2 - v0) * t +
+         (v0 * 2. - v1 * 5. + v2 * 4. - v3) * t2 +
+         (v1 * 3. - v0 - v2 * 3. + v3) * t3) * 0.5)
+}
+
+fn slerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+    let qa = a.quaternion().coords;
+    let mut qb = b.quaternion().coords;
+
+    // take the shortest arc
+    let mut cos = qa.dot(&qb);
+    if cos < 0. {
+        qb = -qb;
+        cos = -cos;
+    }
+
+    let q: Vector4<f32> = if cos > 0.9995 {
+        qa + (qb - qa) * t
+    } else {
+        let angle = cos.acos();
+        let sin = angle.sin();
+        qa * (((1. - t) * angle).sin() / sin) + qb * ((t * angle).sin() / sin)
+    };
+
+    UnitQuaternion::new_normalize(Quaternion::from_vector(q))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::f32::consts::PI;
+    use std::io;
+    use nalgebra::{Point3, Quaternion, UnitQuaternion, Vector3};
+    use scene::Sphere;
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, angle: f32) -> Keyframe {
+        Keyframe {
+            time: time,
+            position: Point3::new(x, 2. * x, -x),
+            orientation: UnitQuaternion::new(Vector3::y() * angle),
+        }
+    }
+
+    fn path(interpolation: Interpolation) -> CameraPath {
+        let mut path = CameraPath::new();
+        path.interpolation = interpolation;
+        path.push(keyframe(0., 0., 0.));
+        path.push(keyframe(1., 1., 0.5 * PI));
+        path.push(keyframe(2., 2., PI));
+        path.push(keyframe(3., 3., 0.));
+        path
+    }
+
+    fn close(a: &Point3<f32>, b: &Point3<f32>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    fn same_rotation(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>) -> bool {
+        let (a, b) = (a.quaternion().coords, b.quaternion().coords);
+        (a - b).norm() < 1e-5 || (a + b).norm() < 1e-5
+    }
+
+    #[test]
+    fn save_and_load() {
+        let saved = path(Interpolation::Linear);
+        let file = env::temp_dir().join("primus_polygoni_camera_path_test.txt");
+        saved.save(&file).unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded.keyframes().len(), saved.keyframes().len());
+        for (l, s) in loaded.keyframes().iter().zip(saved.keyframes()) {
+            assert_eq!(l.time, s.time);
+            assert_eq!(l.position, s.position);
+            assert!(same_rotation(&l.orientation, &s.orientation));
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let invalid = |text: &str| CameraPath::parse(text).unwrap_err().kind();
+        assert_eq!(invalid(""), io::ErrorKind::InvalidData);
+        assert_eq!(invalid("# only a comment\n"), io::ErrorKind::InvalidData);
+        assert_eq!(invalid("0 1 2 3 1 0 0"), io::ErrorKind::InvalidData);
+        assert_eq!(invalid("0 1 2 3 1 0 0 zero"), io::ErrorKind::InvalidData);
+        assert_eq!(invalid("1 0 0 0 1 0 0 0\n0.5 0 0 0 1 0 0 0"), io::ErrorKind::InvalidData);
+
+        let path = CameraPath::parse("0 0 0 0 1 0 0 0\n\n1 0 0 0 1 0 0 0\n1 1 0 0 1 0 0 0").unwrap();
+        assert_eq!(path.keyframes().len(), 3);
+        assert_eq!(path.duration(), 1.);
+    }
+
+    #[test]
+    fn clamped() {
+        assert_eq!(CameraPath::new().sample(0.), None);
+
+        let path = path(Interpolation::CatmullRom);
+        let (first, last) = (path.keyframes()[0], path.keyframes()[3]);
+        assert_eq!(path.sample(-1.), Some((first.position, first.orientation)));
+        assert_eq!(path.sample(4.), Some((last.position, last.orientation)));
+    }
+
+    #[test]
+    fn through_keyframes() {
+        for &interpolation in &[Interpolation::Linear, Interpolation::CatmullRom] {
+            let path = path(interpolation);
+            for k in path.keyframes() {
+                let (position, orientation) = path.sample(k.time).unwrap();
+                assert!(close(&position, &k.position), "{:?} at {}", interpolation, k.time);
+                assert!(same_rotation(&orientation, &k.orientation));
+            }
+        }
+    }
+
+    #[test]
+    fn linear() {
+        let path = path(Interpolation::Linear);
+        let (position, orientation) = path.sample(0.5).unwrap();
+        assert!(close(&position, &Point3::new(0.5, 1., -0.5)));
+        assert!(same_rotation(&orientation, &UnitQuaternion::new(Vector3::y() * 0.25 * PI)));
+
+        let (position, _) = path.sample(2.5).unwrap();
+        assert!(close(&position, &Point3::new(2.5, 5., -2.5)));
+    }
+
+    #[test]
+    fn catmull_rom_on_a_line() {
+        // evenly spaced keyframes on a line are followed at constant speed
+        let path = path(Interpolation::CatmullRom);
+        let (position, _) = path.sample(1.25).unwrap();
+        assert!(close(&position, &Point3::new(1.25, 2.5, -1.25)));
+    }
+
+    #[test]
+    fn apply() {
+        let bounds = Sphere { center: Point3::new(0., 0., 0.), radius: 10. };
+        let mut camera = Camera::new(&bounds);
+
+        let mut single = CameraPath::new();
+        single.push(keyframe(0.5, 1., 0.5 * PI));
+        for &time in &[0., 0.5, 1., 100.] {
+            assert!(single.apply(&mut camera, time), "single keyframe over at {}", time);
+            assert!(close(&camera.position(), &single.keyframes()[0].position));
+        }
+
+        let path = path(Interpolation::Linear);
+        assert!(path.apply(&mut camera, 3.));
+        assert!(close(&camera.position(), &path.keyframes()[3].position));
+        assert!(!path.apply(&mut camera, 3.5));
+    }
+
+    #[test]
+    fn shortest_arc() {
+        let a = UnitQuaternion::new(Vector3::y() * 0.1);
+        // the same rotation as `Vector3::y() * 0.3`, on the other side of the hypersphere
+        let b = UnitQuaternion::new(Vector3::y() * 0.3).quaternion().coords;
+        let b = UnitQuaternion::new_normalize(Quaternion::from_vector(-b));
+        let half = slerp(&a, &b, 0.5);
+        assert!(same_rotation(&half, &UnitQuaternion::new(Vector3::y() * 0.2)));
+    }
+}
@@ -0,0 +1,153 @@
+use nalgebra::{Point3, Vector3};
+use scene::Sphere;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    /// Normalized.
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin: origin,
+            direction: direction / direction.norm(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    /// Distance along the ray to the first intersection with `sphere`,
+    /// if it is not behind the origin.
+    pub fn intersect_sphere(&self, sphere: &Sphere) -> Option<f32> {
+        let oc = self.origin - sphere.center;
+        let b = oc.dot(&self.direction);
+        let c = oc.norm_squared() - sphere.radius * sphere.radius;
+        let discriminant = b * b - c;
+        if discriminant < 0. {
+            return None;
+        }
+
+        let sqrt = discriminant.sqrt();
+        let (near, far) = (-b - sqrt, -b + sqrt);
+        if near >= 0. {
+            Some(near)
+        } else if far >= 0. {
+            // the origin is inside the sphere
+            Some(far)
+        } else {
+            None
+        }
+    }
+}
+
+/// Index of the first sphere hit by `ray`, and the distance to it.
+pub fn pick(ray: &Ray, spheres: &[Sphere]) -> Option<(usize, f32)> {
+    spheres.iter()
+        .enumerate()
+        .filter_map(|(i, s)| ray.intersect_sphere(s).map(|t| (i, t)))
+        .fold(None, |best: Option<(usize, f32)>, (i, t)| match best {
+            Some((_, best_t)) if best_t <= t => best,
+            _ => Some((i, t)),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector2, Vector3, Vector4};
+    use camera::{Camera, Projection};
+    use scene::Sphere;
+    use super::*;
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> Sphere {
+        Sphere { center: Point3::new(x, y, z), radius: radius }
+    }
+
+    fn camera(projection: Projection) -> Camera {
+        let mut camera = Camera::new(&sphere(0., 0., 0., 10.));
+        camera.set_projection(projection);
+        camera.update(16. / 9.);
+        camera
+    }
+
+    const SCREEN: [[f32; 2]; 5] = [[0., 0.], [-0.5, -0.5], [0.5, -0.5], [-0.5, 0.5], [0.5, 0.5]];
+
+    /// Rays go through the normalized device coordinates they were cast from.
+    fn check_projection(camera: &Camera) {
+        for s in &SCREEN {
+            let ray = camera.ray(Vector2::new(s[0], s[1]));
+            assert!((ray.direction.norm() - 1.).abs() < 1e-5);
+
+            let p = ray.at(5.);
+            let clip = camera.transform() * Vector4::new(p.x, p.y, p.z, 1.);
+            let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+            assert!((x - 2. * s[0]).abs() < 1e-4 && (y - 2. * s[1]).abs() < 1e-4,
+                    "{:?} went through ({}, {})", s, x, y);
+        }
+    }
+
+    #[test]
+    fn perspective_rays() {
+        let camera = camera(Projection::Perspective);
+        check_projection(&camera);
+
+        let center = camera.ray(Vector2::new(0., 0.));
+        assert_eq!(center.origin, camera.position());
+        assert!((center.direction - camera.ahead()).norm() < 1e-5);
+        for s in &SCREEN {
+            assert_eq!(camera.ray(Vector2::new(s[0], s[1])).origin, camera.position());
+        }
+    }
+
+    #[test]
+    fn orthographic_rays() {
+        let camera = camera(Projection::Orthographic { height: 8. });
+        check_projection(&camera);
+
+        let center = camera.ray(Vector2::new(0., 0.));
+        assert!((center.origin - camera.position()).norm() < 1e-5);
+        for s in &SCREEN {
+            let ray = camera.ray(Vector2::new(s[0], s[1]));
+            assert!((ray.direction - camera.ahead()).norm() < 1e-5);
+        }
+        let corner = camera.ray(Vector2::new(0.5, 0.5));
+        let offset = corner.origin - camera.position();
+        assert!((offset.dot(&camera.right()) - 4. * 16. / 9.).abs() < 1e-4);
+        assert!((offset.dot(&camera.up()) - 4.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn intersect_sphere() {
+        let ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 2.));
+        assert_eq!(ray.direction, Vector3::new(0., 0., 1.));
+
+        assert_eq!(ray.intersect_sphere(&sphere(0., 0., 5., 1.)), Some(4.));
+        // from inside, the way out
+        assert_eq!(ray.intersect_sphere(&sphere(0., 0., 0.5, 1.)), Some(1.5));
+        // behind
+        assert_eq!(ray.intersect_sphere(&sphere(0., 0., -5., 1.)), None);
+        // beside
+        assert_eq!(ray.intersect_sphere(&sphere(2., 0., 5., 1.)), None);
+        // grazing
+        assert_eq!(ray.intersect_sphere(&sphere(1., 0., 5., 1.)), Some(5.));
+    }
+
+    #[test]
+    fn nearest_hit() {
+        let ray = Ray::new(Point3::new(0., 0., 0.), Vector3::new(0., 0., 1.));
+        let spheres = [
+            sphere(0., 0., 10., 1.),
+            sphere(3., 0., 2., 1.),
+            sphere(0., 0., 4., 1.),
+            sphere(0., 0., -2., 1.),
+            sphere(0., 0., 4., 1.),
+        ];
+        assert_eq!(pick(&ray, &spheres), Some((2, 3.)));
+        assert_eq!(pick(&ray, &spheres[..2]), Some((0, 9.)));
+        assert_eq!(pick(&ray, &spheres[1..2]), None);
+        assert_eq!(pick(&ray, &[]), None);
+    }
+}
@@ -0,0 +1,186 @@
+use allocator::Slot;
+
+/// Outcome of `TexturePool::request`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Residency {
+    /// The sphere texture is already in this slot.
+    Hit(Slot),
+    /// The sphere got this slot, whose texture must be generated again.
+    /// `evicted` is the sphere that held it before.
+    Miss { slot: Slot, evicted: Option<usize> },
+}
+
+impl Residency {
+    pub fn slot(&self) -> Slot {
+        match *self {
+            Residency::Hit(slot) => slot,
+            Residency::Miss { slot, .. } => slot,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PoolSlot {
+    slot: Slot,
+    sphere: Option<usize>,
+    /// Frame of the last request, 0 if never requested.
+    last_used: u64,
+}
+
+/// Keeps the textures of the spheres that need them the most in fewer
+/// texture slots than there are spheres, evicting the least recently used.
+///
+/// Everything is decided on the CPU from the order of the requests,
+/// so the same requests always lead to the same assignment.
+#[derive(Debug, Clone)]
+pub struct TexturePool {
+    slots: Vec<PoolSlot>,
+    /// Index in `slots` of each sphere, if resident.
+    residents: Vec<Option<usize>>,
+    frame: u64,
+}
+
+impl TexturePool {
+    pub fn new(slots: Vec<Slot>, sphere_count: usize) -> Self {
+        TexturePool {
+            slots: slots.into_iter()
+                .map(|slot| PoolSlot { slot: slot, sphere: None, last_used: 0 })
+                .collect(),
+            residents: vec![None; sphere_count],
+            frame: 1,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Slots requested from now on belong to a new frame;
+    /// slots requested during the current frame are never evicted.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn slot(&self, sphere: usize) -> Option<Slot> {
+        self.residents[sphere].map(|s| self.slots[s].slot)
+    }
+
+    pub fn resident_count(&self) -> usize {
+        self.slots.iter().filter(|s| s.sphere.is_some()).count()
+    }
+
+    /// Makes `sphere` resident, unless every slot was already requested
+    /// during this frame.
+    pub fn request(&mut self, sphere: usize) -> Option<Residency> {
+        if let Some(s) = self.residents[sphere] {
+            self.slots[s].last_used = self.frame;
+            return Some(Residency::Hit(self.slots[s].slot));
+        }
+
+        // empty slots first, then the least recently used
+        let frame = self.frame;
+        let victim = self.slots.iter().enumerate()
+            .filter(|&(_, s)| s.last_used < frame)
+            .min_by_key(|&(_, s)| (s.sphere.is_some(), s.last_used))
+            .map(|(i, _)| i);
+
+        victim.map(|s| {
+            let evicted = self.slots[s].sphere;
+            if let Some(evicted) = evicted {
+                self.residents[evicted] = None;
+            }
+            self.slots[s].sphere = Some(sphere);
+            self.slots[s].last_used = frame;
+            self.residents[sphere] = Some(s);
+            Residency::Miss { slot: self.slots[s].slot, evicted: evicted }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allocator::Slot;
+    use super::*;
+
+    /// Spheres on the x axis, at x = index.
+    const SPHERES: usize = 8;
+
+    fn slot(layer: usize) -> Slot {
+        Slot { array: 0, layer: layer }
+    }
+
+    /// Requests the spheres within `radius` of the camera at `x`,
+    /// nearest first, as `Scene` does for the visible ones.
+    fn frame(pool: &mut TexturePool, x: f32, radius: f32) -> Vec<(usize, Option<Residency>)> {
+        let mut visible: Vec<usize> = (0..SPHERES)
+            .filter(|&i| (i as f32 - x).abs() <= radius)
+            .collect();
+        visible.sort_by(|&a, &b| {
+            (a as f32 - x).abs().partial_cmp(&(b as f32 - x).abs()).unwrap()
+        });
+
+        pool.next_frame();
+        visible.into_iter().map(|i| (i, pool.request(i))).collect()
+    }
+
+    fn hit(layer: usize) -> Option<Residency> {
+        Some(Residency::Hit(slot(layer)))
+    }
+
+    fn miss(layer: usize, evicted: Option<usize>) -> Option<Residency> {
+        Some(Residency::Miss { slot: slot(layer), evicted: evicted })
+    }
+
+    #[test]
+    fn camera_flight() {
+        let mut pool = TexturePool::new((0..3).map(slot).collect(), SPHERES);
+        assert_eq!(pool.capacity(), 3);
+        assert_eq!(pool.resident_count(), 0);
+
+        // empty slots first
+        assert_eq!(frame(&mut pool, 0., 1.), vec![(0, miss(0, None)), (1, miss(1, None))]);
+        assert_eq!(frame(&mut pool, 1., 1.),
+                   vec![(1, hit(1)), (0, hit(0)), (2, miss(2, None))]);
+        assert_eq!(pool.resident_count(), 3);
+
+        // all three used last frame, the first of them goes
+        assert_eq!(frame(&mut pool, 3., 1.),
+                   vec![(3, miss(0, Some(0))), (2, hit(2)), (4, miss(1, Some(1)))]);
+        assert_eq!(pool.slot(0), None);
+        assert_eq!(pool.slot(1), None);
+
+        // only sphere 3 is used, 2 and 4 become the least recently used
+        assert_eq!(frame(&mut pool, 3., 0.5), vec![(3, hit(0))]);
+
+        // 4 is the least recently used, while 3 was kept by the last frame;
+        // then spheres 0 and 4 find every slot already requested this frame
+        assert_eq!(frame(&mut pool, 2., 2.),
+                   vec![(2, hit(2)), (1, miss(1, Some(4))), (3, hit(0)), (0, None), (4, None)]);
+        assert_eq!(pool.slot(4), None);
+        assert_eq!(pool.slot(0), None);
+        assert_eq!(pool.resident_count(), 3);
+
+        // a new frame frees them again
+        assert_eq!(frame(&mut pool, 0., 0.5), vec![(0, miss(0, Some(3)))]);
+        assert_eq!(pool.slot(0), Some(slot(0)));
+        assert_eq!(pool.slot(1), Some(slot(1)));
+        assert_eq!(pool.slot(2), Some(slot(2)));
+        assert_eq!(pool.slot(3), None);
+    }
+
+    #[test]
+    fn same_requests_same_slots() {
+        let flight = [(0., 1.), (4., 2.), (7., 1.), (2., 3.), (5., 0.5), (1., 8.)];
+        let run = || {
+            let mut pool = TexturePool::new((0..3).map(slot).collect(), SPHERES);
+            flight.iter().map(|&(x, radius)| frame(&mut pool, x, radius)).collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn residency_slot() {
+        assert_eq!(hit(1).unwrap().slot(), slot(1));
+        assert_eq!(miss(2, Some(5)).unwrap().slot(), slot(2));
+    }
+}
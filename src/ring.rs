@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+
+/// Tells when the GPU is done with the commands submitted before a fence.
+pub trait FenceSource {
+    type Fence;
+
+    /// Blocks until the GPU goes past `fence`.
+    fn wait(&mut self, fence: &Self::Fence);
+}
+
+/// Allocations submitted together, retired together.
+#[derive(Debug)]
+struct Epoch<F> {
+    fence: F,
+    /// Elements allocated, including the padding skipped on wraparound.
+    size: usize,
+}
+
+/// Sub-allocates a buffer of `capacity` elements as a ring: allocations
+/// are made after the previous ones and freed in the same order, once the
+/// fence of their submission is reached.
+///
+/// Allocations are never split on wraparound, the end of the buffer is
+/// skipped instead.
+#[derive(Debug)]
+pub struct RingAllocator<F> {
+    capacity: usize,
+    /// Where the next allocation starts.
+    head: usize,
+    /// Where the oldest allocation still in use starts.
+    tail: usize,
+    /// Elements between `tail` and `head`.
+    used: usize,
+    /// Elements allocated since the last `submit`.
+    pending: usize,
+    epochs: VecDeque<Epoch<F>>,
+}
+
+impl<F> RingAllocator<F> {
+    pub fn new(capacity: usize) -> Self {
+        RingAllocator {
+            capacity: capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+            pending: 0,
+            epochs: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Elements in use, by the GPU or by allocations not submitted yet.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    fn fits(&self, size: usize) -> bool {
+        if self.used == 0 {
+            size <= self.capacity
+        } else if self.head > self.tail {
+            size <= self.capacity - self.head || size <= self.tail
+        } else {
+            // wrapped around, or full when `head == tail`
+            size <= self.tail - self.head
+        }
+    }
+
+    fn retire_oldest(&mut self) {
+        if let Some(epoch) = self.epochs.pop_front() {
+            self.used -= epoch.size;
+            self.tail = (self.tail + epoch.size) % self.capacity;
+        }
+    }
+
+    /// Offset of `size` free elements, waiting for the GPU to release
+    /// older allocations if needed.
+    ///
+    /// Returns `None` when the allocations not submitted yet leave no room.
+    pub fn allocate<S>(&mut self, size: usize, fences: &mut S) -> Option<usize>
+        where S: FenceSource<Fence=F>
+    {
+        if size > self.capacity {
+            return None;
+        }
+
+        while !self.fits(size) {
+            match self.epochs.front() {
+                Some(epoch) => fences.wait(&epoch.fence),
+                None => return None,
+            }
+            self.retire_oldest();
+        }
+
+        if self.used == 0 {
+            // start over, rather than wrapping around later
+            self.head = 0;
+            self.tail = 0;
+        } else if self.head > self.tail && size > self.capacity - self.head {
+            let padding = self.capacity - self.head;
+            self.used += padding;
+            self.pending += padding;
+            self.head = 0;
+        }
+
+        let offset = self.head;
+        self.head = (self.head + size) % self.capacity;
+        self.used += size;
+        self.pending += size;
+        Some(offset)
+    }
+
+    /// Closes the allocations made since the last call,
+    /// which are free once the GPU goes past `fence`.
+    pub fn submit(&mut self, fence: F) {
+        if self.pending > 0 {
+            self.epochs.push_back(Epoch { fence: fence, size: self.pending });
+            self.pending = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fences are numbers, waiting on one records it.
+    struct Fences {
+        waited: Vec<u32>,
+    }
+
+    impl FenceSource for Fences {
+        type Fence = u32;
+
+        fn wait(&mut self, fence: &u32) {
+            self.waited.push(*fence);
+        }
+    }
+
+    fn fences() -> Fences {
+        Fences { waited: Vec::new() }
+    }
+
+    #[test]
+    fn waits_on_the_oldest_first() {
+        let mut fences = fences();
+        let mut ring = RingAllocator::new(10);
+        assert_eq!(ring.allocate(4, &mut fences), Some(0));
+        ring.submit(1);
+        assert_eq!(ring.allocate(3, &mut fences), Some(4));
+        ring.submit(2);
+        assert!(fences.waited.is_empty());
+        assert_eq!(ring.used(), 7);
+
+        // neither the end nor the start has room until both are retired
+        assert_eq!(ring.allocate(5, &mut fences), Some(0));
+        assert_eq!(fences.waited, vec![1, 2]);
+        assert_eq!(ring.used(), 5);
+    }
+
+    #[test]
+    fn wraparound_skips_the_end() {
+        let mut fences = fences();
+        let mut ring = RingAllocator::new(10);
+        ring.allocate(4, &mut fences);
+        ring.submit(1);
+        ring.allocate(4, &mut fences);
+        ring.submit(2);
+
+        // 2 left at the end, 4 at the start once the first epoch is done
+        assert_eq!(ring.allocate(3, &mut fences), Some(0));
+        assert_eq!(fences.waited, vec![1]);
+        // the padding counts as used until the allocation is retired
+        assert_eq!(ring.used(), 4 + 2 + 3);
+        ring.submit(3);
+
+        assert_eq!(ring.allocate(1, &mut fences), Some(3));
+        assert_eq!(ring.used(), 10);
+        // full, the second epoch has to go
+        assert_eq!(ring.allocate(1, &mut fences), Some(4));
+        assert_eq!(fences.waited, vec![1, 2]);
+        assert_eq!(ring.used(), 2 + 3 + 2);
+        ring.submit(4);
+
+        // retiring the third epoch, padding included, ends right after it
+        assert_eq!(ring.allocate(6, &mut fences), Some(0));
+        assert_eq!(fences.waited, vec![1, 2, 3, 4]);
+        assert_eq!(ring.used(), 6);
+    }
+
+    #[test]
+    fn unsubmitted_allocations_fill_the_ring() {
+        let mut fences = fences();
+        let mut ring = RingAllocator::new(10);
+        assert_eq!(ring.allocate(6, &mut fences), Some(0));
+        assert_eq!(ring.allocate(5, &mut fences), None);
+        assert!(fences.waited.is_empty());
+
+        ring.submit(1);
+        assert_eq!(ring.allocate(5, &mut fences), Some(0));
+        assert_eq!(fences.waited, vec![1]);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut fences = fences();
+        let mut ring = RingAllocator::new(10);
+        assert_eq!(ring.allocate(11, &mut fences), None);
+        assert_eq!(ring.allocate(10, &mut fences), Some(0));
+        ring.submit(1);
+        assert_eq!(ring.allocate(11, &mut fences), None);
+        assert!(fences.waited.is_empty());
+        assert_eq!(ring.capacity(), 10);
+    }
+
+    #[test]
+    fn starts_over_when_empty() {
+        let mut fences = fences();
+        let mut ring = RingAllocator::new(10);
+        ring.allocate(3, &mut fences);
+        ring.submit(1);
+        ring.allocate(3, &mut fences);
+        ring.submit(2);
+
+        // once everything is retired, the ring starts over at 0
+        assert_eq!(ring.allocate(7, &mut fences), Some(0));
+        assert_eq!(fences.waited, vec![1, 2]);
+
+        ring.submit(3);
+        // submitting nothing makes no epoch to wait on
+        ring.submit(4);
+        assert_eq!(ring.allocate(3, &mut fences), Some(7));
+        assert_eq!(fences.waited, vec![1, 2]);
+    }
+}
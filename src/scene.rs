@@ -1,8 +1,14 @@
-use std::f32::consts::PI;
+use std::cmp;
 use {gfx, rand};
-use rand::distributions::{IndependentSample, Range};
-use nalgebra::{self, Vector3};
-use Camera;
+use rand::{Rng, XorShiftRng};
+use nalgebra::{self, Point3};
+use {Camera, Recipe, InstanceLayout, Lighting, mesh, gpu};
+use animation::{self, Animation};
+use atmosphere::Atmosphere;
+use skybox::Skybox;
+use allocator::{LayerAllocator, Slot};
+use pool::{TexturePool, Residency};
+use virtual_texture::VirtualTexture;
 
 pub type ColorFormat = gfx::format::Rgba8;
 pub type DepthFormat = gfx::format::DepthStencil;
@@ -10,6 +16,7 @@ pub type DepthFormat = gfx::format::DepthStencil;
 gfx_defines! {
     vertex Vertex {
         pos: [f32; 4] = "a_Pos",
+        normal: [f32; 3] = "a_Normal",
         tex_coord: [f32; 2] = "a_TexCoord",
     }
 
@@ -18,10 +25,22 @@ gfx_defines! {
         t2: [f32; 4] = "a_T2",
         t3: [f32; 4] = "a_T3",
         t4: [f32; 4] = "a_T4",
+        radius: f32 = "a_Radius",
+        tint: [f32; 4] = "a_Tint",
+        layer: u32 = "a_Layer",
+        emissive: u32 = "a_Emissive",
+        page_table: i32 = "a_PageTable",
+        atmosphere: [f32; 4] = "a_Atmosphere",
     }
 
     constant Locals {
         transform: [[f32; 4]; 4] = "u_Transform",
+        selected: [i32; 4] = "u_Selected",
+        camera_position: [f32; 4] = "u_CameraPosition",
+        sun_direction: [f32; 4] = "u_SunDirection",
+        sun_color: [f32; 4] = "u_SunColor",
+        ambient: [f32; 4] = "u_Ambient",
+        virtual_pages: [f32; 4] = "u_VirtualPages",
     }
 
     pipeline pipe {
@@ -29,49 +48,215 @@ gfx_defines! {
         instances: gfx::InstanceBuffer<Instance> = (),
         locals: gfx::ConstantBuffer<Locals> = "Locals",
         color: gfx::TextureSampler<[f32; 4]> = "t_Color",
+        page_cache: gfx::TextureSampler<[f32; 4]> = "t_PageCache",
+        page_table: gfx::TextureSampler<[f32; 4]> = "t_PageTable",
         color_target: gfx::RenderTarget<ColorFormat> = "Target0",
         depth_target: gfx::DepthTarget<DepthFormat> =
             gfx::preset::depth::LESS_EQUAL_WRITE,
     }
+
+    pipeline atmosphere_pipe {
+        vertices: gfx::VertexBuffer<Vertex> = (),
+        instances: gfx::InstanceBuffer<Instance> = (),
+        locals: gfx::ConstantBuffer<Locals> = "Locals",
+        color_target: gfx::BlendTarget<ColorFormat> =
+            ("Target0", gfx::state::MASK_ALL, gfx::preset::blend::ADD),
+        depth_target: gfx::DepthTarget<DepthFormat> =
+            gfx::preset::depth::LESS_EQUAL_TEST,
+    }
 }
 
 impl Vertex {
-    pub fn new(pos: [f32; 3], tex_coord: [f32; 2]) -> Vertex {
+    pub fn new(pos: [f32; 3], normal: [f32; 3], tex_coord: [f32; 2]) -> Vertex {
         Vertex {
             pos: [pos[0], pos[1], pos[2], 1.0],
+            normal: normal,
             tex_coord: tex_coord,
         }
     }
 }
 
-pub trait Impl<R: gfx::Resources>: Sized {
-    fn init<F: gfx::Factory<R>>(_w: usize,
+/// Bounding sphere of an instance, in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+/// How a sphere is drawn, independently of where it is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Appearance {
+    /// Multiplies the texture color.
+    pub tint: [f32; 4],
+    /// Emissive spheres glow with their own color and ignore lighting.
+    pub emissive: bool,
+}
+
+const STAR_COUNT: usize = 9000;
+
+/// How textures are uploaded, see `strategy`.
+pub trait Impl<R: gfx::Resources> {
+    /// Allocates what uploads to `a` layers of `w` by `h` texels need,
+    /// over all texture arrays.
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                _w: usize,
                                 _h: usize,
                                 _a: usize,
-                                _factory: &mut F) -> Self;
-    fn texture_bind() -> gfx::memory::Bind;
-    fn texture_usage() -> gfx::memory::Usage;
-    fn generate_textures<C, F>(scene: &mut Scene<R, Self>,
-                               encoder: &mut gfx::Encoder<R, C>,
-                               factory: &mut F)
-        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>;
+                                _factory: &mut F);
+    fn texture_bind(&self) -> gfx::memory::Bind;
+    fn texture_usage(&self) -> gfx::memory::Usage;
+    /// Generates and uploads every layer of `arrays`, from their seeds.
+    /// `device` waits on fences, or submits `encoder` before the end.
+    fn generate_textures<C, F, D>(&mut self,
+                                  arrays: &[TextureArray<R>],
+                                  texture_size: usize,
+                                  encoder: &mut gfx::Encoder<R, C>,
+                                  factory: &mut F,
+                                  device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        let slots: Vec<_> = arrays.iter().enumerate()
+            .flat_map(|(a, array)| {
+                (0..array.layers()).map(move |layer| Slot { array: a, layer: layer })
+            })
+            .collect();
+        self.generate_layers(arrays, &slots, texture_size, encoder, factory, device);
+    }
+
+    /// Same as `generate_textures`, for the layers at `slots` only,
+    /// sorted by array then layer and without duplicates.
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>;
+
+    /// Called once the commands recorded by `generate_textures`
+    /// are submitted, with a fence signaled when they are done.
+    fn submitted(&mut self, _fence: gfx::handle::Fence<R>) {}
+
+    /// Seconds the last `generate_textures` spent waiting on fences.
+    fn fence_wait(&self) -> f64 {
+        0.
+    }
+}
+
+fn create_array_texture<R, F>(factory: &mut F,
+                              w: usize,
+                              h: usize,
+                              layers: usize,
+                              bind: gfx::memory::Bind,
+                              usage: gfx::memory::Usage)
+                              -> (gfx::handle::Texture<R, gfx::format::R8_G8_B8_A8>,
+                                  gfx::handle::ShaderResourceView<R, [f32; 4]>)
+    where R: gfx::Resources, F: gfx::Factory<R>
+{
+    let texture =
+        factory.create_texture(
+            gfx::texture::Kind::D2Array(w as gfx::texture::Size,
+                                        h as gfx::texture::Size,
+                                        layers as gfx::texture::Size,
+                                        gfx::texture::AaMode::Single),
+            1,
+            gfx::memory::SHADER_RESOURCE | bind,
+            usage,
+            Some(gfx::format::ChannelType::Unorm)
+        ).expect("could not create scene texture");
+    let view = factory
+        .view_texture_as_shader_resource::<gfx::format::Rgba8>(&texture, (0, 0), gfx::format::Swizzle::new())
+        .expect("could not create scene texture view");
+    (texture, view)
+}
+
+/// A texture array and the instances sampling it, drawn in one batch.
+pub struct TextureArray<R: gfx::Resources> {
+    pub texture: gfx::handle::Texture<R, gfx::format::R8_G8_B8_A8>,
+    /// Texture seed of each layer.
+    pub seeds: Vec<u32>,
+    data: pipe::Data<R>,
+    /// Shares its buffers with `data`.
+    atmosphere_data: atmosphere_pipe::Data<R>,
+    slice: gfx::Slice<R>,
+    /// Instances of the batch, and the sphere each one comes from.
+    batch: Vec<Instance>,
+    batch_spheres: Vec<usize>,
+}
+
+impl<R: gfx::Resources> TextureArray<R> {
+    pub fn layers(&self) -> usize {
+        self.seeds.len()
+    }
+
+    /// Instance of the `selected` sphere in the batch, -1 if not in it.
+    fn selected(&self, selected: Option<usize>) -> i32 {
+        selected
+            .and_then(|selected| self.batch_spheres.iter().position(|&i| i == selected))
+            .map(|i| i as i32)
+            .unwrap_or(-1)
+    }
 }
 
 pub struct Scene<R: gfx::Resources, I: Impl<R>> {
     pub sphere_count: usize,
     pub scene_radius: f32,
+    /// Contains all spheres.
+    pub bounds: Sphere,
     pub texture_size: usize,
     pub camera: Camera,
+    pub spheres: Vec<Sphere>,
+    animation: Animation,
+    pub animating: bool,
+    pub lighting: Lighting,
+    pub appearances: Vec<Appearance>,
+    pub atmospheres: Vec<Atmosphere>,
+    /// Draws the atmospheres after the spheres.
+    pub atmosphere_pass: bool,
+    /// Texture seed of each sphere.
+    pub seeds: Vec<u32>,
+    pub layers: LayerAllocator,
+    pub arrays: Vec<TextureArray<R>>,
+    pub pool: TexturePool,
+    /// Drawn in place of the spheres that got no slot from the pool.
+    fallback: Slot,
+    /// Layers assigned to other spheres, whose seeds changed.
+    stale_slots: Vec<Slot>,
+    visible_spheres: Vec<usize>,
+    /// Higher resolution textures for the planets closest to the camera.
+    pub virtual_texture: VirtualTexture<R>,
+    selected: Option<usize>,
+    instances: Vec<Instance>,
+    culling: bool,
+    instances_dirty: bool,
     pub pso: gfx::PipelineState<R, pipe::Meta>,
-    pub data: pipe::Data<R>,
-    pub slice: gfx::Slice<R>,
-    pub texture: gfx::handle::Texture<R, gfx::format::R8_G8_B8_A8>,
+    /// Same as `pso`, for projections with reversed depth.
+    pub pso_reverse_z: gfx::PipelineState<R, pipe::Meta>,
+    pub atmosphere_pso: gfx::PipelineState<R, atmosphere_pipe::Meta>,
+    pub atmosphere_pso_reverse_z: gfx::PipelineState<R, atmosphere_pipe::Meta>,
+    locals: gfx::handle::Buffer<R, Locals>,
+    /// Copied to each batch on `render`.
+    pub color_target: gfx::handle::RenderTargetView<R, ColorFormat>,
+    pub depth_target: gfx::handle::DepthStencilView<R, DepthFormat>,
+    pub skybox: Option<Skybox<R>>,
     pub implementation: I,
 }
 
 impl<R: gfx::Resources, I: Impl<R>> Scene<R, I> {
-    pub fn new<F, C>(sphere_count: usize,
+    /// Spreads the sphere textures over arrays of at most `max_layers` layers.
+    /// Only `texture_count` textures are resident at once,
+    /// shared by the spheres closest to the camera.
+    /// With `sky`, a skybox is drawn behind them, taking one more layer.
+    pub fn new<F, C>(mut implementation: I,
+                     sphere_count: usize,
                      texture_size: usize,
+                     texture_count: usize,
+                     max_layers: usize,
+                     layout: &InstanceLayout,
+                     sky: bool,
                      color_target: gfx::handle::RenderTargetView<R, ColorFormat>,
                      depth_target: gfx::handle::DepthStencilView<R, DepthFormat>,
                      factory: &mut F,
@@ -91,108 +276,378 @@ impl<R: gfx::Resources, I: Impl<R>> Scene<R, I> {
             gfx::state::Rasterizer::new_fill(),
             pipe::new()
         ).expect("could not create scene pipeline");
+        let pso_reverse_z = factory.create_pipeline_from_program(
+            &program,
+            gfx::Primitive::TriangleList,
+            gfx::state::Rasterizer::new_fill(),
+            pipe::Init {
+                depth_target: gfx::state::Depth {
+                    fun: gfx::state::Comparison::GreaterEqual,
+                    write: true,
+                },
+                .. pipe::new()
+            }
+        ).expect("could not create reverse-Z scene pipeline");
+
+        let atmosphere_program = factory.link_program(
+            include_bytes!("shader/atmosphere_150.glslv"),
+            include_bytes!("shader/atmosphere_150.glslf")
+        ).expect("could not create atmosphere program");
+
+        let atmosphere_pso = factory.create_pipeline_from_program(
+            &atmosphere_program,
+            gfx::Primitive::TriangleList,
+            gfx::state::Rasterizer::new_fill(),
+            atmosphere_pipe::new()
+        ).expect("could not create atmosphere pipeline");
+        let atmosphere_pso_reverse_z = factory.create_pipeline_from_program(
+            &atmosphere_program,
+            gfx::Primitive::TriangleList,
+            gfx::state::Rasterizer::new_fill(),
+            atmosphere_pipe::Init {
+                depth_target: gfx::state::Depth {
+                    fun: gfx::state::Comparison::GreaterEqual,
+                    write: false,
+                },
+                .. atmosphere_pipe::new()
+            }
+        ).expect("could not create reverse-Z atmosphere pipeline");
 
         let (vertex_data, index_data) = ::generate_icosphere(4);
-        let (vertices, mut slice) = factory
+        let (vertex_data, index_data) = mesh::optimize(&vertex_data, &index_data);
+        let (vertices, slice) = factory
             .create_vertex_buffer_with_slice(&vertex_data[..], &index_data[..]);
-        slice.instances = Some((sphere_count as u32, 0));
+        let locals = factory.create_constant_buffer(1);
 
-        let instances = factory.create_buffer(sphere_count,
-                                              gfx::buffer::Role::Vertex,
-                                              gfx::memory::Usage::Dynamic,
-                                              gfx::Bind::empty()).unwrap();
+        let mut rng: XorShiftRng = rand::thread_rng().gen();
+        let mut spheres = layout.place(sphere_count, &mut rng);
+        let animation = Animation::new(&mut spheres, layout.moons(), &mut rng);
+        let mut bounds = layout.bounds(&spheres);
+        if layout.moons() {
+            bounds.radius += animation::MOON_MARGIN;
+        }
 
-        let (w, h) = (texture_size * 2, texture_size);
-        let texture =
-            factory.create_texture(
-                gfx::texture::Kind::D2Array(w as gfx::texture::Size,
-                                            h as gfx::texture::Size,
-                                            sphere_count as gfx::texture::Size,
-                                            gfx::texture::AaMode::Single),
-                1,
-                gfx::memory::SHADER_RESOURCE | I::texture_bind(),
-                I::texture_usage(),
-                Some(gfx::format::ChannelType::Unorm)
-            ).expect("could not create scene texture");
-        let texture_view = factory
-            .view_texture_as_shader_resource::<gfx::format::Rgba8>(&texture, (0, 0), gfx::format::Swizzle::new())
-            .expect("could not create scene texture view");
+        let mut layers = LayerAllocator::new(max_layers);
+        let fallback = layers.allocate();
+        let sky_slot = if sky { Some(layers.allocate()) } else { None };
+        let slots = (0..cmp::min(texture_count, sphere_count)).map(|_| layers.allocate()).collect();
+        let mut pool = TexturePool::new(slots, sphere_count);
+        for sphere in 0..sphere_count {
+            pool.request(sphere);
+        }
+
+        let appearances = animation.motions.iter().map(|motion| Appearance {
+            // moons are a little duller
+            tint: if motion.orbit.is_some() { [0.8, 0.8, 0.8, 1.] } else { [1.; 4] },
+            emissive: false,
+        }).collect();
+        // moons are too small to hold an atmosphere
+        let atmospheres = animation.motions.iter().map(|motion| {
+            if motion.orbit.is_some() { Atmosphere::none() } else { Atmosphere::random(&mut rng) }
+        }).collect();
 
+        let (w, h) = (texture_size * 2, texture_size);
         let sinfo = gfx::texture::SamplerInfo::new(
             gfx::texture::FilterMethod::Bilinear,
             gfx::texture::WrapMode::Clamp);
+        let sampler = factory.create_sampler(sinfo);
+        let nearest_sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Scale,
+            gfx::texture::WrapMode::Clamp));
+        let virtual_texture = VirtualTexture::new(factory);
+
+        let mut arrays: Vec<_> = (0..layers.array_count()).map(|a| {
+            let array_layers = layers.array_layers(a);
+            let (texture, texture_view) = create_array_texture(
+                factory, w, h, array_layers,
+                implementation.texture_bind(), implementation.texture_usage());
+
+            // every sphere may end up in the same batch
+            let instances = factory.create_buffer(sphere_count,
+                                                  gfx::buffer::Role::Vertex,
+                                                  gfx::memory::Usage::Dynamic,
+                                                  gfx::Bind::empty()).unwrap();
 
-        let radius = (sphere_count as f32 * 4.0) / (2.0 * PI);
-        let scene = Scene {
+            TextureArray {
+                texture: texture,
+                seeds: vec![0; array_layers],
+                data: pipe::Data {
+                    vertices: vertices.clone(),
+                    instances: instances.clone(),
+                    locals: locals.clone(),
+                    color: (texture_view, sampler.clone()),
+                    page_cache: (virtual_texture.cache_view.clone(), sampler.clone()),
+                    page_table: (virtual_texture.table_view.clone(), nearest_sampler.clone()),
+                    color_target: color_target.clone(),
+                    depth_target: depth_target.clone(),
+                },
+                atmosphere_data: atmosphere_pipe::Data {
+                    vertices: vertices.clone(),
+                    instances: instances,
+                    locals: locals.clone(),
+                    color_target: color_target.clone(),
+                    depth_target: depth_target.clone(),
+                },
+                slice: slice.clone(),
+                batch: Vec::with_capacity(sphere_count),
+                batch_spheres: Vec::with_capacity(sphere_count),
+            }
+        }).collect();
+        implementation.init(w, h, layers.allocated(), factory);
+
+        let skybox = sky_slot.map(|slot| {
+            let seed = rng.gen();
+            arrays[slot.array].seeds[slot.layer] = seed;
+            Skybox::new(seed, slot, STAR_COUNT, arrays[slot.array].data.color.clone(),
+                        color_target.clone(), factory)
+        });
+
+        let mut scene = Scene {
             sphere_count: sphere_count,
-            scene_radius: radius,
+            scene_radius: bounds.radius,
+            bounds: bounds,
             texture_size: texture_size,
-            camera: Camera::new(radius),
+            camera: Camera::new(&bounds),
+            spheres: spheres,
+            animation: animation,
+            animating: false,
+            lighting: Lighting::default(),
+            appearances: appearances,
+            atmospheres: atmospheres,
+            atmosphere_pass: true,
+            seeds: vec![0; sphere_count],
+            layers: layers,
+            arrays: arrays,
+            pool: pool,
+            fallback: fallback,
+            stale_slots: Vec::new(),
+            visible_spheres: Vec::with_capacity(sphere_count),
+            virtual_texture: virtual_texture,
+            selected: None,
+            instances: Vec::with_capacity(sphere_count),
+            culling: false,
+            instances_dirty: false,
             pso: pso,
-            data: pipe::Data {
-                vertices: vertices,
-                instances: instances,
-                locals: factory.create_constant_buffer(1),
-                color: (texture_view, factory.create_sampler(sinfo)),
-                color_target: color_target,
-                depth_target: depth_target,
-            },
-            slice: slice,
-            texture: texture,
-            implementation: I::init(w, h, sphere_count, factory),
+            pso_reverse_z: pso_reverse_z,
+            atmosphere_pso: atmosphere_pso,
+            atmosphere_pso_reverse_z: atmosphere_pso_reverse_z,
+            locals: locals,
+            color_target: color_target,
+            depth_target: depth_target,
+            skybox: skybox,
+            implementation: implementation,
         };
 
-        scene.fill_instances(encoder);
+        scene.write_instances();
+        scene.write_batches(encoder);
         scene
     }
 
-    fn fill_instances<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    fn write_instances(&mut self) {
+        self.instances.clear();
+        let parts = self.spheres.iter()
+            .zip(&self.animation.rotations)
+            .zip(&self.appearances)
+            .zip(&self.atmospheres);
+        for (((sphere, rotation), appearance), atmosphere) in parts {
+            // the radius is applied separately, so that normals
+            // can go through the transform as is
+            let transform = nalgebra::Isometry3::from_parts(
+                nalgebra::Translation3::from_vector(sphere.center.coords),
+                *rotation).to_homogeneous();
+            let columns = gpu::mat4(&transform);
+
+            self.instances.push(Instance {
+                t1: columns[0],
+                t2: columns[1],
+                t3: columns[2],
+                t4: columns[3],
+                radius: sphere.radius,
+                tint: appearance.tint,
+                // set for each batch, depending on texture residency
+                layer: 0,
+                emissive: appearance.emissive as u32,
+                page_table: -1,
+                atmosphere: atmosphere.gpu(),
+            });
+        }
+    }
+
+    /// Sorts the instances into the batch of their texture array,
+    /// skipping those outside of the view frustum when culling.
+    ///
+    /// Drawn spheres request their texture from the pool, nearest first.
+    /// Those whose slot is still stale are drawn with the fallback texture.
+    fn write_batches<C>(&mut self, encoder: &mut gfx::Encoder<R, C>)
         where C: gfx::CommandBuffer<R>
     {
-        let mut vec = Vec::with_capacity(self.sphere_count);
-        let mut rng = rand::thread_rng();
+        use nalgebra::distance_squared;
 
-        for i in 0..self.sphere_count {
-            let angle = i as f32 / self.sphere_count as f32 * (2.0 * PI);
-            let position = Vector3::new(
-                angle.cos() * self.scene_radius,
-                0.0,
-                angle.sin() * self.scene_radius);
-
-            let radius = Range::new(0.5, 1.5).ind_sample(&mut rng);
-            let remaining = 2.0 - radius;
-
-            let range = Range::new(-remaining, remaining);
-            let displacement = Vector3::new(
-                range.ind_sample(&mut rng),
-                range.ind_sample(&mut rng),
-                range.ind_sample(&mut rng),
-            );
-
-            let transform = nalgebra::Similarity3::from_parts(
-                nalgebra::Translation3::from_vector(position + displacement),
-                nalgebra::one(),
-                radius).to_homogeneous();
-            let transform = transform.as_slice();
-
-            let line = |l: &[f32]| [l[0], l[1], l[2], l[3]];
-
-            vec.push(Instance {
-                t1: line(&transform[0..4]),
-                t2: line(&transform[4..8]),
-                t3: line(&transform[8..12]),
-                t4: line(&transform[12..16]),
-            });
+        for array in &mut self.arrays {
+            array.batch.clear();
+            array.batch_spheres.clear();
+        }
+
+        let frustum = self.camera.frustum();
+        self.visible_spheres.clear();
+        let parts = self.spheres.iter().zip(&self.atmospheres);
+        for (i, (sphere, atmosphere)) in parts.enumerate() {
+            let radius = sphere.radius * (1. + atmosphere.thickness);
+            if !self.culling || frustum.intersects_sphere(&sphere.center, radius) {
+                self.visible_spheres.push(i);
+            }
+        }
+        let eye = self.camera.position();
+        let spheres = &self.spheres;
+        self.visible_spheres.sort_by(|&a, &b| {
+            distance_squared(&spheres[a].center, &eye)
+                .partial_cmp(&distance_squared(&spheres[b].center, &eye))
+                .unwrap_or(cmp::Ordering::Equal)
+        });
+
+        self.pool.next_frame();
+        for &i in &self.visible_spheres {
+            let slot = match self.pool.request(i) {
+                Some(Residency::Hit(slot)) => slot,
+                Some(Residency::Miss { slot, .. }) => {
+                    self.arrays[slot.array].seeds[slot.layer] = self.seeds[i];
+                    self.stale_slots.push(slot);
+                    slot
+                }
+                None => self.fallback,
+            };
+            // the slot holds the texture of another sphere until `update_textures`
+            let slot = if self.stale_slots.contains(&slot) { self.fallback } else { slot };
+
+            let mut instance = self.instances[i];
+            instance.layer = slot.layer as u32;
+            instance.page_table = self.virtual_texture.table(i).map(|t| t as i32).unwrap_or(-1);
+            let array = &mut self.arrays[slot.array];
+            array.batch.push(instance);
+            array.batch_spheres.push(i);
+        }
+
+        for array in &mut self.arrays {
+            if !array.batch.is_empty() {
+                encoder.update_buffer(&array.data.instances, &array.batch[..], 0).unwrap();
+            }
+            array.slice.instances = Some((array.batch.len() as u32, 0));
         }
-        encoder.update_buffer(&self.data.instances, &vec[..], 0).unwrap();
     }
 
-    pub fn generate_textures<C, F>(&mut self,
-                                   encoder: &mut gfx::Encoder<R, C>,
-                                   factory: &mut F)
-        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>
+    /// Spins the spheres and moves the moons along their orbits.
+    /// The instance buffers are streamed again on the next `render`.
+    pub fn animate(&mut self, delta: f32) {
+        if !self.animating {
+            return;
+        }
+        self.animation.update(&mut self.spheres, delta);
+        self.write_instances();
+        self.instances_dirty = true;
+    }
+
+    pub fn culling(&self) -> bool {
+        self.culling
+    }
+
+    /// Only draws the spheres intersecting the view frustum.
+    pub fn set_culling(&mut self, culling: bool) {
+        if self.culling && !culling {
+            self.instances_dirty = true;
+        }
+        self.culling = culling;
+    }
+
+    /// Number of spheres drawn by the last `render`.
+    pub fn drawn_count(&self) -> usize {
+        self.arrays.iter().map(|array| array.batch.len()).sum()
+    }
+
+    /// Generates new textures for all spheres,
+    /// returns the number of layers handed to the strategy.
+    pub fn generate_textures<C, F, D>(&mut self,
+                                      encoder: &mut gfx::Encoder<R, C>,
+                                      factory: &mut F,
+                                      device: &mut D) -> usize
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
     {
-        I::generate_textures(self, encoder, factory);
+        let mut rng = rand::thread_rng();
+        for seed in &mut self.seeds {
+            *seed = rng.gen();
+        }
+        self.arrays[self.fallback.array].seeds[self.fallback.layer] = rng.gen();
+        self.virtual_texture.clear();
+        for sphere in 0..self.sphere_count {
+            if let Some(slot) = self.pool.slot(sphere) {
+                self.arrays[slot.array].seeds[slot.layer] = self.seeds[sphere];
+            }
+        }
+        self.stale_slots.clear();
+        self.implementation.generate_textures(&self.arrays, self.texture_size,
+                                              encoder, factory, device);
+        self.arrays.iter().map(|array| array.layers()).sum()
+    }
+
+    /// Whether the pool gave slots to other spheres since the last
+    /// texture update, which `update_textures` takes care of.
+    pub fn textures_stale(&self) -> bool {
+        !self.stale_slots.is_empty()
+    }
+
+    /// Generates the textures of the layers the pool gave to other spheres,
+    /// returns their number.
+    pub fn update_textures<C, F, D>(&mut self,
+                                    encoder: &mut gfx::Encoder<R, C>,
+                                    factory: &mut F,
+                                    device: &mut D) -> usize
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        self.stale_slots.sort_by_key(|slot| (slot.array, slot.layer));
+        self.stale_slots.dedup();
+        self.implementation.generate_layers(&self.arrays, &self.stale_slots, self.texture_size,
+                                            encoder, factory, device);
+        let count = self.stale_slots.len();
+        self.stale_slots.clear();
+        count
+    }
+
+    /// Switches to another upload strategy, which recreates the textures
+    /// for its bind flags and usage: they must be generated again.
+    pub fn set_implementation<F>(&mut self, mut implementation: I, factory: &mut F)
+        where F: gfx::Factory<R>
+    {
+        let (w, h) = (self.texture_size * 2, self.texture_size);
+        for array in &mut self.arrays {
+            let (texture, view) = create_array_texture(
+                factory, w, h, array.layers(),
+                implementation.texture_bind(), implementation.texture_usage());
+            array.texture = texture;
+            array.data.color.0 = view;
+        }
+        implementation.init(w, h, self.layers.allocated(), factory);
+        self.implementation = implementation;
+    }
+
+    pub fn recipe(&self, sphere: usize) -> Recipe {
+        Recipe::from_seed(self.seeds[sphere])
+    }
+
+    /// Instance attributes are written again on the next `render`.
+    pub fn set_appearance(&mut self, sphere: usize, appearance: Appearance) {
+        self.appearances[sphere] = appearance;
+        self.write_instances();
+        self.instances_dirty = true;
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Highlights a sphere.
+    pub fn select(&mut self, sphere: Option<usize>) {
+        self.selected = sphere;
     }
 
     pub fn render<C>(&mut self,
@@ -201,10 +656,130 @@ impl<R: gfx::Resources, I: Impl<R>> Scene<R, I> {
         where C: gfx::CommandBuffer<R>
     {
         self.camera.update(aspect_ratio);
-        encoder.update_constant_buffer(&self.data.locals, &Locals {
-            transform: self.camera.gpu_transform()
-        });
 
-        encoder.draw(&self.slice, &self.pso, &self.data);
+        if let Some(ref mut skybox) = self.skybox {
+            let nebula = &self.arrays[skybox.slot.array].data.color.0;
+            skybox.render(&self.camera, nebula, &self.color_target, encoder);
+        }
+
+        let (_, viewport_height, _, _) = self.color_target.get_dimensions();
+        let tables_changed = self.virtual_texture.update(&self.spheres,
+                                                         &self.animation.rotations,
+                                                         &self.seeds,
+                                                         &self.camera,
+                                                         viewport_height as f32,
+                                                         encoder);
+        // with fewer slots than spheres, the nearest spheres change as the camera moves
+        let pooled = self.pool.capacity() < self.sphere_count;
+        if self.culling || pooled || self.instances_dirty || tables_changed {
+            self.write_batches(encoder);
+            self.instances_dirty = false;
+        }
+
+        let reversed_depth = self.camera.projection().reversed_depth();
+        let (pso, atmosphere_pso) = if reversed_depth {
+            (&self.pso_reverse_z, &self.atmosphere_pso_reverse_z)
+        } else {
+            (&self.pso, &self.atmosphere_pso)
+        };
+        let mut locals = Locals {
+            transform: self.camera.gpu_transform(),
+            selected: [-1, 0, 0, 0],
+            camera_position: gpu::point4(&self.camera.position()),
+            sun_direction: self.lighting.gpu_sun_direction(),
+            sun_color: self.lighting.gpu_sun_color(),
+            ambient: self.lighting.gpu_ambient(),
+            virtual_pages: self.virtual_texture.gpu_params(),
+        };
+
+        for array in &mut self.arrays {
+            // the targets may have been replaced on resize
+            array.data.color_target = self.color_target.clone();
+            array.data.depth_target = self.depth_target.clone();
+            array.atmosphere_data.color_target = self.color_target.clone();
+            array.atmosphere_data.depth_target = self.depth_target.clone();
+        }
+
+        // atmospheres blend over whatever is behind them,
+        // so every planet must be drawn before any of them
+        for array in self.arrays.iter().filter(|array| !array.batch.is_empty()) {
+            locals.selected[0] = array.selected(self.selected);
+            encoder.update_constant_buffer(&self.locals, &locals);
+            encoder.draw(&array.slice, pso, &array.data);
+        }
+        if self.atmosphere_pass {
+            for array in self.arrays.iter().filter(|array| !array.batch.is_empty()) {
+                locals.selected[0] = array.selected(self.selected);
+                encoder.update_constant_buffer(&self.locals, &locals);
+                encoder.draw(&array.slice, atmosphere_pso, &array.atmosphere_data);
+            }
+        }
+    }
+
+    /// Index of the sphere closest to `point`.
+    pub fn nearest_sphere(&self, point: &Point3<f32>) -> Option<usize> {
+        use nalgebra::distance_squared;
+
+        self.spheres.iter()
+            .map(|s| distance_squared(&s.center, point))
+            .enumerate()
+            .fold(None, |best: Option<(usize, f32)>, (i, d)| match best {
+                Some((_, best_d)) if best_d <= d => best,
+                _ => Some((i, d)),
+            })
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx;
+    use gfx_core::Factory as CoreFactory;
+    use layout;
+    use null;
+    use strategy::Strategy;
+    use super::*;
+
+    /// Layer the batches draw `sphere` with, and its array.
+    fn drawn_slot<R, I>(scene: &Scene<R, I>, sphere: usize) -> Slot
+        where R: gfx::Resources, I: Impl<R>
+    {
+        scene.arrays.iter().enumerate()
+            .filter_map(|(a, array)| {
+                array.batch_spheres.iter().position(|&i| i == sphere)
+                    .map(|j| Slot { array: a, layer: array.batch[j].layer as usize })
+            })
+            .next()
+            .expect("sphere not drawn")
+    }
+
+    #[test]
+    fn pool_follows_the_camera() {
+        let (mut device, mut factory) = null::create();
+        let (_, _, color_target) = factory.create_render_target::<ColorFormat>(64, 32).unwrap();
+        let (_, _, depth_target) = factory.create_depth_stencil::<DepthFormat>(64, 32).unwrap();
+        let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
+        let layout = layout::from_name("ring").unwrap();
+        let mut scene = Scene::new(Strategy::from_name("null").unwrap(),
+                                   16, 8, 4, 64, &*layout, false,
+                                   color_target, depth_target,
+                                   &mut factory, &mut encoder);
+        scene.generate_textures(&mut encoder, &mut factory, &mut device);
+        scene.render(2., &mut encoder);
+        assert!(!scene.textures_stale());
+
+        let far = (0..scene.sphere_count).find(|&i| scene.pool.slot(i).is_none()).unwrap();
+        let center = scene.spheres[far].center;
+        scene.camera.set_position(center);
+        scene.render(2., &mut encoder);
+        // the nearest sphere took a slot, but draws the fallback until regenerated
+        let slot = scene.pool.slot(far).unwrap();
+        assert!(scene.stale_slots.contains(&slot));
+        assert_eq!(drawn_slot(&scene, far), scene.fallback);
+
+        assert!(scene.update_textures(&mut encoder, &mut factory, &mut device) > 0);
+        assert!(!scene.textures_stale());
+        scene.render(2., &mut encoder);
+        assert_eq!(drawn_slot(&scene, far), slot);
     }
 }
@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+use gfx;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use nalgebra::Vector3;
+use allocator::Slot;
+use scene::ColorFormat;
+use {Camera, gpu};
+
+gfx_defines! {
+    vertex SkyVertex {
+        pos: [f32; 2] = "a_Pos",
+    }
+
+    vertex StarVertex {
+        direction: [f32; 3] = "a_Direction",
+        color: [f32; 3] = "a_Color",
+    }
+
+    constant SkyLocals {
+        right: [f32; 4] = "u_Right",
+        up: [f32; 4] = "u_Up",
+        ahead: [f32; 4] = "u_Ahead",
+        nebula: [f32; 4] = "u_Nebula",
+    }
+
+    pipeline sky_pipe {
+        vertices: gfx::VertexBuffer<SkyVertex> = (),
+        locals: gfx::ConstantBuffer<SkyLocals> = "SkyLocals",
+        nebula: gfx::TextureSampler<[f32; 4]> = "t_Nebula",
+        color_target: gfx::RenderTarget<ColorFormat> = "Target0",
+    }
+
+    pipeline star_pipe {
+        vertices: gfx::VertexBuffer<StarVertex> = (),
+        locals: gfx::ConstantBuffer<SkyLocals> = "SkyLocals",
+        color_target: gfx::BlendTarget<ColorFormat> =
+            ("Target0", gfx::state::MASK_ALL, gfx::preset::blend::ADD),
+    }
+}
+
+/// Apparent magnitude of the brightest star.
+const MIN_MAGNITUDE: f32 = -1.;
+/// Apparent magnitude of the faintest star, which is about what
+/// the naked eye can see.
+const MAX_MAGNITUDE: f32 = 6.5;
+/// Displayed intensity of the faintest stars, about 20 / 255.
+const MIN_INTENSITY: f32 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Star {
+    pub direction: Vector3<f32>,
+    pub magnitude: f32,
+    pub color: [f32; 3],
+}
+
+impl Star {
+    /// Brightness relative to the brightest star: each magnitude is
+    /// 2.512 times fainter than the previous one.
+    pub fn brightness(&self) -> f32 {
+        10f32.powf(-0.4 * (self.magnitude - MIN_MAGNITUDE))
+    }
+
+    /// Brightness as displayed, from 1 down to `MIN_INTENSITY`.
+    ///
+    /// Magnitudes already follow how the eye perceives brightness,
+    /// while `brightness` would leave all but the brightest stars
+    /// below what 8 bits per channel can show.
+    pub fn intensity(&self) -> f32 {
+        let t = (self.magnitude - MIN_MAGNITUDE) / (MAX_MAGNITUDE - MIN_MAGNITUDE);
+        1. - t * (1. - MIN_INTENSITY)
+    }
+}
+
+/// Uniformly spreads `count` stars over the sky, with as many stars
+/// of each magnitude as if they were uniformly spread in space:
+/// there are about 4 times more stars at each magnitude than at the previous one.
+pub fn generate_stars(count: usize, rng: &mut XorShiftRng) -> Vec<Star> {
+    // from the hottest to the coolest
+    const COLORS: [[f32; 3]; 5] = [
+        [0.7, 0.8, 1.0],
+        [0.9, 0.9, 1.0],
+        [1.0, 1.0, 1.0],
+        [1.0, 0.9, 0.7],
+        [1.0, 0.7, 0.5],
+    ];
+
+    (0..count).map(|_| {
+        let z = rng.gen_range(-1f32, 1.);
+        let phi = rng.gen_range(0., 2. * PI);
+        let r = (1. - z * z).sqrt();
+
+        // inverse of the cumulative distribution N(< m) ∝ 10^(0.6 m)
+        let u = rng.gen_range(1e-6f32, 1.);
+        let magnitude = (MAX_MAGNITUDE + u.log10() / 0.6).max(MIN_MAGNITUDE);
+
+        Star {
+            direction: Vector3::new(r * phi.cos(), r * phi.sin(), z),
+            magnitude: magnitude,
+            color: COLORS[rng.gen_range(0, COLORS.len())],
+        }
+    }).collect()
+}
+
+/// Background drawn behind everything else: a faint nebula and stars.
+///
+/// The nebula is a texture array layer at `slot`, generated from `seed`
+/// and uploaded by the strategy like the planet textures,
+/// the stars are points generated once since they never change.
+pub struct Skybox<R: gfx::Resources> {
+    pub seed: u32,
+    pub slot: Slot,
+    pso: gfx::PipelineState<R, sky_pipe::Meta>,
+    data: sky_pipe::Data<R>,
+    slice: gfx::Slice<R>,
+    star_pso: gfx::PipelineState<R, star_pipe::Meta>,
+    star_data: star_pipe::Data<R>,
+    star_slice: gfx::Slice<R>,
+}
+
+impl<R: gfx::Resources> Skybox<R> {
+    pub fn new<F>(seed: u32,
+                  slot: Slot,
+                  star_count: usize,
+                  nebula: (gfx::handle::ShaderResourceView<R, [f32; 4]>,
+                           gfx::handle::Sampler<R>),
+                  color_target: gfx::handle::RenderTargetView<R, ColorFormat>,
+                  factory: &mut F) -> Self
+        where F: gfx::Factory<R>
+    {
+        use gfx::traits::FactoryExt;
+
+        let pso = factory.create_pipeline_simple(
+            include_bytes!("shader/sky_150.glslv"),
+            include_bytes!("shader/sky_150.glslf"),
+            sky_pipe::new()
+        ).expect("could not create sky pipeline");
+
+        let star_program = factory.link_program(
+            include_bytes!("shader/star_150.glslv"),
+            include_bytes!("shader/star_150.glslf")
+        ).expect("could not create star program");
+        let star_pso = factory.create_pipeline_from_program(
+            &star_program,
+            gfx::Primitive::PointList,
+            gfx::state::Rasterizer::new_fill(),
+            star_pipe::new()
+        ).expect("could not create star pipeline");
+
+        // a single triangle covering the screen
+        let vertex_data = [
+            SkyVertex { pos: [-1., -1.] },
+            SkyVertex { pos: [3., -1.] },
+            SkyVertex { pos: [-1., 3.] },
+        ];
+        let (vertices, slice) = factory.create_vertex_buffer_with_slice(&vertex_data, ());
+
+        let mut rng = XorShiftRng::from_seed([seed, 0x3C6EF372, 0xA54FF53A, 0x510E527F]);
+        let star_data: Vec<_> = generate_stars(star_count, &mut rng).iter().map(|star| {
+            let i = star.intensity();
+            StarVertex {
+                direction: [star.direction.x, star.direction.y, star.direction.z],
+                color: [star.color[0] * i, star.color[1] * i, star.color[2] * i],
+            }
+        }).collect();
+        let (star_vertices, star_slice) =
+            factory.create_vertex_buffer_with_slice(&star_data[..], ());
+
+        let locals = factory.create_constant_buffer(1);
+        Skybox {
+            seed: seed,
+            slot: slot,
+            pso: pso,
+            data: sky_pipe::Data {
+                vertices: vertices,
+                locals: locals.clone(),
+                nebula: nebula,
+                color_target: color_target.clone(),
+            },
+            slice: slice,
+            star_pso: star_pso,
+            star_data: star_pipe::Data {
+                vertices: star_vertices,
+                locals: locals,
+                color_target: color_target,
+            },
+            star_slice: star_slice,
+        }
+    }
+
+    /// Draws the sky on `color_target`, with the nebula from `nebula`,
+    /// which is the array holding `slot`.
+    pub fn render<C>(&mut self,
+                     camera: &Camera,
+                     nebula: &gfx::handle::ShaderResourceView<R, [f32; 4]>,
+                     color_target: &gfx::handle::RenderTargetView<R, ColorFormat>,
+                     encoder: &mut gfx::Encoder<R, C>)
+        where C: gfx::CommandBuffer<R>
+    {
+        // the targets may have been replaced on resize,
+        // and the textures when switching strategies
+        self.data.nebula.0 = nebula.clone();
+        self.data.color_target = color_target.clone();
+        self.star_data.color_target = color_target.clone();
+
+        let (right, up, ahead) = camera.sky_basis();
+        encoder.update_constant_buffer(&self.data.locals, &SkyLocals {
+            right: gpu::direction4(&right),
+            up: gpu::direction4(&up),
+            ahead: gpu::direction4(&ahead),
+            nebula: [self.slot.layer as f32, 0., 0., 0.],
+        });
+        encoder.draw(&self.slice, &self.pso, &self.data);
+        encoder.draw(&self.star_slice, &self.star_pso, &self.star_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+    use super::*;
+
+    #[test]
+    fn stars() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let stars = generate_stars(10_000, &mut rng);
+        assert_eq!(stars.len(), 10_000);
+
+        let mut faint = 0;
+        for star in &stars {
+            assert!((star.direction.norm() - 1.).abs() < 1e-4);
+            assert!(star.magnitude >= MIN_MAGNITUDE && star.magnitude <= MAX_MAGNITUDE);
+            if star.magnitude > MAX_MAGNITUDE - 1. {
+                faint += 1;
+            }
+        }
+        // 1 - 10^-0.6 of the stars are in the faintest magnitude
+        assert!(faint > 7_000 && faint < 8_000, "{} faint stars", faint);
+    }
+
+    #[test]
+    fn visible_intensity() {
+        let star = |magnitude| Star {
+            direction: Vector3::new(0., 0., 1.),
+            magnitude: magnitude,
+            color: [1.; 3],
+        };
+        assert_eq!(star(MIN_MAGNITUDE).intensity(), 1.);
+        assert!((star(MAX_MAGNITUDE).intensity() - MIN_INTENSITY).abs() < 1e-6);
+        // still at least one step of an 8 bit channel
+        assert!((star(MAX_MAGNITUDE).intensity() * 255.) as u8 >= 1);
+        assert!(star(2.).intensity() > star(3.).intensity());
+
+        assert_eq!(star(MIN_MAGNITUDE).brightness(), 1.);
+        assert!((star(MIN_MAGNITUDE + 5.).brightness() - 0.01).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,56 @@
+use gfx;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use texture;
+
+/// Updates each layer from memory.
+pub struct Dynamic;
+
+impl<R: gfx::Resources> Impl<R> for Dynamic {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                _w: usize,
+                                _h: usize,
+                                _a: usize,
+                                _factory: &mut F) {}
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::Bind::empty()
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Dynamic
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                _: &mut F,
+                                _: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        let (w, h) = (texture_size * 2, texture_size);
+        let mut texels: Vec<_> = (0..(w * h)).map(|_| [0; 4]).collect();
+        let mut info = gfx::texture::ImageInfoCommon {
+            xoffset: 0,
+            yoffset: 0,
+            zoffset: 0,
+            width: w as gfx::texture::Size,
+            height: h as gfx::texture::Size,
+            depth: 1,
+            format: (),
+            mipmap: 0
+        };
+
+        for slot in slots {
+            let array = &arrays[slot.array];
+            texture::generate(&mut texels[..], texture_size, array.seeds[slot.layer]);
+            info.zoffset = slot.layer as gfx::texture::Size;
+            encoder.update_texture::<_, gfx::format::Rgba8>
+                (&array.texture, None, info, &texels[..])
+                .unwrap();
+        }
+    }
+}
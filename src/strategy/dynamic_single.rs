@@ -0,0 +1,66 @@
+use gfx;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use texture;
+use super::layer_runs;
+
+/// Updates each texture array at once from memory.
+/// Partial updates cover consecutive layers at once.
+pub struct DynamicSingle;
+
+impl<R: gfx::Resources> Impl<R> for DynamicSingle {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                _w: usize,
+                                _h: usize,
+                                _a: usize,
+                                _factory: &mut F) {}
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::Bind::empty()
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Dynamic
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                _: &mut F,
+                                _: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        let (w, h) = (texture_size * 2, texture_size);
+
+        // one update per run of consecutive layers, so one per texture array
+        // when everything is generated
+        for (a, first, count) in layer_runs(slots) {
+            let array = &arrays[a];
+            let mut buffer: Vec<_> = (0..(w * h * count))
+                .map(|_| [0; 4]).collect();
+
+            let seeds = &array.seeds[first..first + count];
+            for (texels, &seed) in buffer.chunks_mut(w * h).zip(seeds) {
+                texture::generate(texels, texture_size, seed);
+            }
+
+            let info = gfx::texture::ImageInfoCommon {
+                xoffset: 0,
+                yoffset: 0,
+                zoffset: first as gfx::texture::Size,
+                width: w as gfx::texture::Size,
+                height: h as gfx::texture::Size,
+                depth: count as gfx::texture::Size,
+                format: (),
+                mipmap: 0
+            };
+
+            encoder.update_texture::<_, gfx::format::Rgba8>
+                (&array.texture, None, info, &buffer[..])
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use gfx;
+use gfx::traits::FactoryExt;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use texture;
+use super::staging::{copy, layers_info};
+
+/// Same as `Staging` with one copy per layer, recorded as soon as
+/// the layer is written rather than after all of them.
+/// With `submit`, each copy is also sent to the GPU right away,
+/// so copies overlap with the generation of the next layers.
+///
+/// Each layer has its own upload buffer: mapping one only waits for
+/// the copies out of it, not for those of the layers before it.
+pub struct Interleaved<R: gfx::Resources> {
+    uploads: Vec<gfx::handle::Buffer<R, [u8; 4]>>,
+    submit: bool,
+}
+
+impl<R: gfx::Resources> Interleaved<R> {
+    pub fn new(submit: bool) -> Self {
+        Interleaved { uploads: Vec::new(), submit: submit }
+    }
+
+    pub fn submits(&self) -> bool {
+        self.submit
+    }
+}
+
+impl<R: gfx::Resources> Impl<R> for Interleaved<R> {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                a: usize,
+                                factory: &mut F) {
+        self.uploads = (0..a).map(|_| {
+            factory.create_upload_buffer(w * h).expect("could not create upload buffer")
+        }).collect();
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::TRANSFER_DST
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Data
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        assert!(slots.len() <= self.uploads.len(), "interleaved strategy used before init");
+        let (w, h) = (texture_size * 2, texture_size);
+
+        for (slot, upload) in slots.iter().zip(&self.uploads) {
+            {
+                let mut writer = factory.write_mapping(upload)
+                    .expect("could not write to mapping");
+                let seed = arrays[slot.array].seeds[slot.layer];
+                texture::generate(&mut writer[..], texture_size, seed);
+            }
+
+            copy(upload, 0, &arrays[slot.array], layers_info(w, h, slot.layer, 1), encoder);
+            if self.submit {
+                encoder.flush(device);
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Ways of uploading generated textures to the GPU, selectable at runtime.
+
+mod dynamic;
+mod dynamic_single;
+mod staging;
+mod multi_staging;
+mod ring;
+mod interleaved;
+mod null;
+
+pub use self::dynamic::Dynamic;
+pub use self::dynamic_single::DynamicSingle;
+pub use self::staging::{Staging, Copies};
+pub use self::multi_staging::MultiStaging;
+pub use self::ring::Ring;
+pub use self::interleaved::Interleaved;
+pub use self::null::NullUploader;
+
+use gfx;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+
+pub const NAMES: &'static [&'static str] = &[
+    "dynamic", "dynamic_single", "staging", "staging_per_layer", "staging_row_blocks",
+    "double_staging", "triple_staging", "ring", "interleaved_staging", "interleaved_submit",
+    "null"
+];
+
+pub enum Strategy<R: gfx::Resources> {
+    Dynamic(Dynamic),
+    DynamicSingle(DynamicSingle),
+    Staging(Staging<R>),
+    MultiStaging(MultiStaging<R>),
+    Ring(Ring<R>),
+    Interleaved(Interleaved<R>),
+    Null(NullUploader),
+}
+
+impl<R: gfx::Resources> Strategy<R> {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dynamic" => Some(Strategy::Dynamic(Dynamic)),
+            "dynamic_single" => Some(Strategy::DynamicSingle(DynamicSingle)),
+            "staging" => Some(Strategy::Staging(Staging::new(Copies::Runs))),
+            "staging_per_layer" => Some(Strategy::Staging(Staging::new(Copies::Layers))),
+            "staging_row_blocks" => Some(Strategy::Staging(Staging::new(Copies::RowBlocks))),
+            "double_staging" => Some(Strategy::MultiStaging(MultiStaging::new(2))),
+            "triple_staging" => Some(Strategy::MultiStaging(MultiStaging::new(3))),
+            "ring" => Some(Strategy::Ring(Ring::new())),
+            "interleaved_staging" => Some(Strategy::Interleaved(Interleaved::new(false))),
+            "interleaved_submit" => Some(Strategy::Interleaved(Interleaved::new(true))),
+            "null" => Some(Strategy::Null(NullUploader::new())),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Strategy::Dynamic(_) => "dynamic",
+            Strategy::DynamicSingle(_) => "dynamic_single",
+            Strategy::Staging(ref s) => match s.copies() {
+                Copies::Runs => "staging",
+                Copies::Layers => "staging_per_layer",
+                Copies::RowBlocks => "staging_row_blocks",
+            },
+            Strategy::MultiStaging(ref s) if s.buffer_count() == 2 => "double_staging",
+            Strategy::MultiStaging(_) => "triple_staging",
+            Strategy::Ring(_) => "ring",
+            Strategy::Interleaved(ref s) if s.submits() => "interleaved_submit",
+            Strategy::Interleaved(_) => "interleaved_staging",
+            Strategy::Null(_) => "null",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match *self {
+            Strategy::Dynamic(_) => "simple memory update (Dynamic)",
+            Strategy::DynamicSingle(_) => "simple memory update, one per array (Dynamic)",
+            Strategy::Staging(ref s) => match s.copies() {
+                Copies::Runs => "memory staging (Upload + Data)",
+                Copies::Layers => "memory staging, one copy per layer (Upload + Data)",
+                Copies::RowBlocks => "memory staging, one copy per row block (Upload + Data)",
+            },
+            Strategy::MultiStaging(ref s) if s.buffer_count() == 2 =>
+                "double buffered memory staging (2 Upload + Data)",
+            Strategy::MultiStaging(_) => "triple buffered memory staging (3 Upload + Data)",
+            Strategy::Ring(_) => "changed layers only, through a ring of upload buffers (Upload + Data)",
+            Strategy::Interleaved(ref s) if s.submits() =>
+                "memory staging, each layer copied and submitted once written (Upload + Data)",
+            Strategy::Interleaved(_) =>
+                "memory staging, each layer copied once written (Upload + Data)",
+            Strategy::Null(_) => "generation only, nothing uploaded",
+        }
+    }
+
+    /// The strategy after this one in `NAMES`, wrapping around.
+    pub fn next(&self) -> Self {
+        let index = NAMES.iter().position(|&name| name == self.name()).unwrap();
+        Strategy::from_name(NAMES[(index + 1) % NAMES.len()]).unwrap()
+    }
+}
+
+/// Runs of consecutive layers of the same array covering `slots`,
+/// sorted by array then layer, as `(array, first layer, layer count)`.
+fn layer_runs(slots: &[Slot]) -> Vec<(usize, usize, usize)> {
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+    for slot in slots {
+        let extends = runs.last()
+            .map(|&(array, first, count)| array == slot.array && first + count == slot.layer)
+            .unwrap_or(false);
+        if extends {
+            runs.last_mut().unwrap().2 += 1;
+        } else {
+            runs.push((slot.array, slot.layer, 1));
+        }
+    }
+    runs
+}
+
+impl<R: gfx::Resources> Impl<R> for Strategy<R> {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                a: usize,
+                                factory: &mut F) {
+        match *self {
+            Strategy::Dynamic(ref mut s) => Impl::<R>::init(s, w, h, a, factory),
+            Strategy::DynamicSingle(ref mut s) => Impl::<R>::init(s, w, h, a, factory),
+            Strategy::Staging(ref mut s) => s.init(w, h, a, factory),
+            Strategy::MultiStaging(ref mut s) => s.init(w, h, a, factory),
+            Strategy::Ring(ref mut s) => s.init(w, h, a, factory),
+            Strategy::Interleaved(ref mut s) => s.init(w, h, a, factory),
+            Strategy::Null(ref mut s) => Impl::<R>::init(s, w, h, a, factory),
+        }
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        match *self {
+            Strategy::Dynamic(ref s) => Impl::<R>::texture_bind(s),
+            Strategy::DynamicSingle(ref s) => Impl::<R>::texture_bind(s),
+            Strategy::Staging(ref s) => s.texture_bind(),
+            Strategy::MultiStaging(ref s) => s.texture_bind(),
+            Strategy::Ring(ref s) => s.texture_bind(),
+            Strategy::Interleaved(ref s) => s.texture_bind(),
+            Strategy::Null(ref s) => Impl::<R>::texture_bind(s),
+        }
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        match *self {
+            Strategy::Dynamic(ref s) => Impl::<R>::texture_usage(s),
+            Strategy::DynamicSingle(ref s) => Impl::<R>::texture_usage(s),
+            Strategy::Staging(ref s) => s.texture_usage(),
+            Strategy::MultiStaging(ref s) => s.texture_usage(),
+            Strategy::Ring(ref s) => s.texture_usage(),
+            Strategy::Interleaved(ref s) => s.texture_usage(),
+            Strategy::Null(ref s) => Impl::<R>::texture_usage(s),
+        }
+    }
+
+    fn generate_textures<C, F, D>(&mut self,
+                                  arrays: &[TextureArray<R>],
+                                  texture_size: usize,
+                                  encoder: &mut gfx::Encoder<R, C>,
+                                  factory: &mut F,
+                                  device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        match *self {
+            Strategy::Dynamic(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::DynamicSingle(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::Staging(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::MultiStaging(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::Ring(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::Interleaved(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+            Strategy::Null(ref mut s) =>
+                s.generate_textures(arrays, texture_size, encoder, factory, device),
+        }
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        match *self {
+            Strategy::Dynamic(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::DynamicSingle(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::Staging(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::MultiStaging(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::Ring(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::Interleaved(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+            Strategy::Null(ref mut s) =>
+                s.generate_layers(arrays, slots, texture_size, encoder, factory, device),
+        }
+    }
+
+    fn submitted(&mut self, fence: gfx::handle::Fence<R>) {
+        match *self {
+            Strategy::Dynamic(ref mut s) => Impl::<R>::submitted(s, fence),
+            Strategy::DynamicSingle(ref mut s) => Impl::<R>::submitted(s, fence),
+            Strategy::Staging(ref mut s) => s.submitted(fence),
+            Strategy::MultiStaging(ref mut s) => s.submitted(fence),
+            Strategy::Ring(ref mut s) => s.submitted(fence),
+            Strategy::Interleaved(ref mut s) => s.submitted(fence),
+            Strategy::Null(ref mut s) => Impl::<R>::submitted(s, fence),
+        }
+    }
+
+    fn fence_wait(&self) -> f64 {
+        match *self {
+            Strategy::Dynamic(ref s) => Impl::<R>::fence_wait(s),
+            Strategy::DynamicSingle(ref s) => Impl::<R>::fence_wait(s),
+            Strategy::Staging(ref s) => s.fence_wait(),
+            Strategy::MultiStaging(ref s) => s.fence_wait(),
+            Strategy::Ring(ref s) => s.fence_wait(),
+            Strategy::Interleaved(ref s) => s.fence_wait(),
+            Strategy::Null(ref s) => Impl::<R>::fence_wait(s),
+        }
+    }
+}
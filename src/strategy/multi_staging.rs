@@ -0,0 +1,101 @@
+use gfx;
+use gfx::traits::FactoryExt;
+use time::precise_time_s;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use super::staging::{stage, Copies};
+
+/// Same as `Staging`, rotating among several upload buffers so that
+/// the next generation can be written while the previous one is copied.
+/// A buffer is only written once the fence of its last copy is reached.
+pub struct MultiStaging<R: gfx::Resources> {
+    uploads: Vec<gfx::handle::Buffer<R, [u8; 4]>>,
+    /// Signaled when the last copy from each buffer is done.
+    fences: Vec<Option<gfx::handle::Fence<R>>>,
+    next: usize,
+    /// Buffer used by the commands not submitted yet.
+    pending: Option<usize>,
+    buffer_count: usize,
+    last_wait: f64,
+    /// Seconds spent waiting on fences since the creation.
+    pub total_wait: f64,
+}
+
+impl<R: gfx::Resources> MultiStaging<R> {
+    pub fn new(buffer_count: usize) -> Self {
+        assert!(buffer_count > 0);
+        MultiStaging {
+            uploads: Vec::new(),
+            fences: Vec::new(),
+            next: 0,
+            pending: None,
+            buffer_count: buffer_count,
+            last_wait: 0.,
+            total_wait: 0.,
+        }
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffer_count
+    }
+}
+
+impl<R: gfx::Resources> Impl<R> for MultiStaging<R> {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                a: usize,
+                                factory: &mut F) {
+        self.uploads = (0..self.buffer_count).map(|_| {
+            factory.create_upload_buffer(w * h * a)
+                .expect("could not create upload buffer")
+        }).collect();
+        self.fences = (0..self.buffer_count).map(|_| None).collect();
+        self.next = 0;
+        self.pending = None;
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::TRANSFER_DST
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Data
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        let current = self.next;
+        self.next = (current + 1) % self.buffer_count;
+
+        self.last_wait = 0.;
+        if let Some(fence) = self.fences[current].take() {
+            let before = precise_time_s();
+            device.wait_fence(&fence);
+            self.last_wait = precise_time_s() - before;
+            self.total_wait += self.last_wait;
+        }
+
+        stage(&self.uploads[current], arrays, slots, texture_size, Copies::Runs,
+              encoder, factory);
+        self.pending = Some(current);
+    }
+
+    fn submitted(&mut self, fence: gfx::handle::Fence<R>) {
+        if let Some(buffer) = self.pending.take() {
+            self.fences[buffer] = Some(fence);
+        }
+    }
+
+    fn fence_wait(&self) -> f64 {
+        self.last_wait
+    }
+}
@@ -0,0 +1,50 @@
+use gfx;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use texture;
+
+/// Generates the layers into memory and uploads nothing,
+/// to measure the generation alone.
+pub struct NullUploader {
+    texels: Vec<[u8; 4]>,
+}
+
+impl NullUploader {
+    pub fn new() -> Self {
+        NullUploader { texels: Vec::new() }
+    }
+}
+
+impl<R: gfx::Resources> Impl<R> for NullUploader {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                _a: usize,
+                                _factory: &mut F) {
+        self.texels = vec![[0; 4]; w * h];
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::Bind::empty()
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Data
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                _: &mut gfx::Encoder<R, C>,
+                                _: &mut F,
+                                _: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        for slot in slots {
+            let seed = arrays[slot.array].seeds[slot.layer];
+            texture::generate(&mut self.texels[..], texture_size, seed);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use gfx;
+use gfx::traits::FactoryExt;
+use time::precise_time_s;
+use allocator::Slot;
+use ring::{FenceSource, RingAllocator};
+use scene::{Impl, TextureArray};
+use texture;
+use super::staging::{copy, layers_info};
+
+/// Waits on the fences of a device, timing it.
+struct DeviceFences<'a, D: 'a> {
+    device: &'a mut D,
+    waited: f64,
+}
+
+impl<'a, R, D> FenceSource for DeviceFences<'a, D>
+    where R: gfx::Resources, D: gfx::Device<Resources=R>
+{
+    type Fence = gfx::handle::Fence<R>;
+
+    fn wait(&mut self, fence: &gfx::handle::Fence<R>) {
+        let before = precise_time_s();
+        self.device.wait_fence(fence);
+        self.waited += precise_time_s() - before;
+    }
+}
+
+/// Uses layer sized upload buffers as the parts of a ring, and only
+/// uploads the layers whose seed changed since their last upload, each
+/// from its own part. A part is written again once the copies using it
+/// are done.
+///
+/// Each part is mapped on its own, so that only the parts being reused
+/// wait for their copies, which is counted in `fence_wait` along with
+/// the ring waits.
+pub struct Ring<R: gfx::Resources> {
+    parts: Vec<gfx::handle::Buffer<R, [u8; 4]>>,
+    /// In parts.
+    ring: RingAllocator<gfx::handle::Fence<R>>,
+    /// Seed of the texture in each layer of each array, if uploaded.
+    uploaded: Vec<Vec<Option<u32>>>,
+    last_wait: f64,
+}
+
+impl<R: gfx::Resources> Ring<R> {
+    pub fn new() -> Self {
+        Ring {
+            parts: Vec::new(),
+            ring: RingAllocator::new(0),
+            uploaded: Vec::new(),
+            last_wait: 0.,
+        }
+    }
+
+    /// Forgets what was uploaded when the textures were recreated.
+    fn track(&mut self, arrays: &[TextureArray<R>]) {
+        if self.uploaded.len() != arrays.len() {
+            self.uploaded = arrays.iter().map(|array| vec![None; array.layers()]).collect();
+        }
+    }
+}
+
+impl<R: gfx::Resources> Impl<R> for Ring<R> {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                a: usize,
+                                factory: &mut F) {
+        // enough for every layer at once, regenerating everything
+        // waits for the previous uploads like `Staging` does
+        self.parts = (0..a).map(|_| {
+            factory.create_upload_buffer(w * h).expect("could not create upload buffer")
+        }).collect();
+        self.ring = RingAllocator::new(a);
+        self.uploaded.clear();
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::TRANSFER_DST
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Data
+    }
+
+    /// Only generates the layers whose seed changed.
+    fn generate_textures<C, F, D>(&mut self,
+                                  arrays: &[TextureArray<R>],
+                                  texture_size: usize,
+                                  encoder: &mut gfx::Encoder<R, C>,
+                                  factory: &mut F,
+                                  device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        self.track(arrays);
+
+        let mut changed = Vec::new();
+        for (a, array) in arrays.iter().enumerate() {
+            for (layer, &seed) in array.seeds.iter().enumerate() {
+                if self.uploaded[a][layer] != Some(seed) {
+                    changed.push(Slot { array: a, layer: layer });
+                }
+            }
+        }
+        self.last_wait = 0.;
+        if !changed.is_empty() {
+            self.generate_layers(arrays, &changed, texture_size, encoder, factory, device);
+        }
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                device: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        self.track(arrays);
+        assert!(!self.parts.is_empty(), "ring strategy used before init");
+        let (w, h) = (texture_size * 2, texture_size);
+
+        let mut fences = DeviceFences { device: device, waited: 0. };
+        let mut parts = Vec::with_capacity(slots.len());
+        for _ in slots {
+            parts.push(self.ring.allocate(1, &mut fences)
+                .expect("upload ring too small for one generation"));
+        }
+        self.last_wait = fences.waited;
+
+        for (slot, &part) in slots.iter().zip(&parts) {
+            // the ring waited for the copies from this part already
+            let before = precise_time_s();
+            let mut writer = factory.write_mapping(&self.parts[part])
+                .expect("could not write to mapping");
+            self.last_wait += precise_time_s() - before;
+            let seed = arrays[slot.array].seeds[slot.layer];
+            texture::generate(&mut writer[..], texture_size, seed);
+        }
+
+        for (slot, &part) in slots.iter().zip(&parts) {
+            let array = &arrays[slot.array];
+            copy(&self.parts[part], 0, array, layers_info(w, h, slot.layer, 1), encoder);
+            self.uploaded[slot.array][slot.layer] = Some(array.seeds[slot.layer]);
+        }
+    }
+
+    fn submitted(&mut self, fence: gfx::handle::Fence<R>) {
+        self.ring.submit(fence);
+    }
+
+    fn fence_wait(&self) -> f64 {
+        self.last_wait
+    }
+}
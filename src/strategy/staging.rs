@@ -0,0 +1,157 @@
+use std::cmp;
+use gfx;
+use gfx::traits::FactoryExt;
+use gfx::memory::Typed;
+use allocator::Slot;
+use scene::{Impl, TextureArray};
+use texture;
+use super::layer_runs;
+
+/// Rows of a layer copied at once with `Copies::RowBlocks`.
+pub const ROW_BLOCK: usize = 32;
+
+/// How many copies go from the upload buffer to the textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Copies {
+    /// One per run of consecutive layers, so one per texture array
+    /// when everything is generated.
+    Runs,
+    /// One per layer.
+    Layers,
+    /// One per block of `ROW_BLOCK` rows of each layer.
+    RowBlocks,
+}
+
+/// Writes all layers into a mapped upload buffer,
+/// then copies it to the textures.
+pub struct Staging<R: gfx::Resources> {
+    upload: Option<gfx::handle::Buffer<R, [u8; 4]>>,
+    copies: Copies,
+}
+
+impl<R: gfx::Resources> Staging<R> {
+    pub fn new(copies: Copies) -> Self {
+        Staging { upload: None, copies: copies }
+    }
+
+    pub fn copies(&self) -> Copies {
+        self.copies
+    }
+}
+
+impl<R: gfx::Resources> Impl<R> for Staging<R> {
+    fn init<F: gfx::Factory<R>>(&mut self,
+                                w: usize,
+                                h: usize,
+                                a: usize,
+                                factory: &mut F) {
+        self.upload = Some(factory.create_upload_buffer(w * h * a)
+            .expect("could not create upload buffer"));
+    }
+
+    fn texture_bind(&self) -> gfx::memory::Bind {
+        gfx::memory::TRANSFER_DST
+    }
+
+    fn texture_usage(&self) -> gfx::memory::Usage {
+        gfx::memory::Usage::Data
+    }
+
+    fn generate_layers<C, F, D>(&mut self,
+                                arrays: &[TextureArray<R>],
+                                slots: &[Slot],
+                                texture_size: usize,
+                                encoder: &mut gfx::Encoder<R, C>,
+                                factory: &mut F,
+                                _: &mut D)
+        where C: gfx::CommandBuffer<R>, F: gfx::Factory<R>,
+              D: gfx::Device<Resources=R, CommandBuffer=C>
+    {
+        let upload = self.upload.as_ref().expect("staging strategy used before init");
+        stage(upload, arrays, slots, texture_size, self.copies, encoder, factory);
+    }
+}
+
+/// Writes the layers at `slots` one after the other into `upload`,
+/// then copies them to their texture arrays.
+pub fn stage<R, C, F>(upload: &gfx::handle::Buffer<R, [u8; 4]>,
+                      arrays: &[TextureArray<R>],
+                      slots: &[Slot],
+                      texture_size: usize,
+                      copies: Copies,
+                      encoder: &mut gfx::Encoder<R, C>,
+                      factory: &mut F)
+    where R: gfx::Resources, C: gfx::CommandBuffer<R>, F: gfx::Factory<R>
+{
+    let (w, h) = (texture_size * 2, texture_size);
+    {
+        let mut writer = factory.write_mapping(upload)
+            .expect("could not write to mapping");
+
+        let seeds = slots.iter().map(|slot| arrays[slot.array].seeds[slot.layer]);
+        for (texels, seed) in writer.chunks_mut(w * h).zip(seeds) {
+            texture::generate(texels, texture_size, seed);
+        }
+    }
+
+    let mut offset = 0;
+    match copies {
+        Copies::Runs => {
+            for (a, first, count) in layer_runs(slots) {
+                copy(upload, offset, &arrays[a], layers_info(w, h, first, count), encoder);
+                offset += w * h * count;
+            }
+        }
+        Copies::Layers => {
+            for slot in slots {
+                let info = layers_info(w, h, slot.layer, 1);
+                copy(upload, offset, &arrays[slot.array], info, encoder);
+                offset += w * h;
+            }
+        }
+        Copies::RowBlocks => {
+            for slot in slots {
+                let mut row = 0;
+                while row < h {
+                    let mut info = layers_info(w, h, slot.layer, 1);
+                    info.yoffset = row as gfx::texture::Size;
+                    info.height = cmp::min(ROW_BLOCK, h - row) as gfx::texture::Size;
+                    copy(upload, offset + row * w, &arrays[slot.array], info, encoder);
+                    row += ROW_BLOCK;
+                }
+                offset += w * h;
+            }
+        }
+    }
+}
+
+/// Region of `count` whole layers of `w` by `h` texels from `first`.
+pub fn layers_info(w: usize, h: usize, first: usize, count: usize)
+                   -> gfx::texture::RawImageInfo {
+    gfx::texture::ImageInfoCommon {
+        xoffset: 0,
+        yoffset: 0,
+        zoffset: first as gfx::texture::Size,
+        width: w as gfx::texture::Size,
+        height: h as gfx::texture::Size,
+        depth: count as gfx::texture::Size,
+        format: <gfx::format::Rgba8 as gfx::format::Formatted>::get_format(),
+        mipmap: 0
+    }
+}
+
+/// Copies `info` of the texture of `array` from `upload`,
+/// where it starts at texel `offset`.
+pub fn copy<R, C>(upload: &gfx::handle::Buffer<R, [u8; 4]>,
+                  offset: usize,
+                  array: &TextureArray<R>,
+                  info: gfx::texture::RawImageInfo,
+                  encoder: &mut gfx::Encoder<R, C>)
+    where R: gfx::Resources, C: gfx::CommandBuffer<R>
+{
+    encoder.copy_buffer_to_texture_raw(
+        // the offset is in bytes, 4 per texel
+        upload.raw(), offset * 4,
+        array.texture.raw(), None, info
+    ).unwrap();
+}
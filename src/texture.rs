@@ -1,67 +1,165 @@
-use std::f32;
-use rand::{self, Rng};
+use std::{f32, fmt};
+use rand::{Rng, SeedableRng, XorShiftRng};
 use noise::{
     NoiseModule, Seedable, MultiFractal, Constant, Fbm, Billow, RidgedMulti
 };
 
-pub fn generate(output: &mut [[u8; 4]], size: usize) {
-    let mut rng = rand::thread_rng();
+/// Parameters of the noise function generating one color channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Noise {
+    Constant { value: f32 },
+    Fbm { seed: usize, octaves: usize, frequency: f32, lacunarity: f32, persistence: f32 },
+    Billow { seed: usize, octaves: usize, frequency: f32, lacunarity: f32, persistence: f32 },
+    RidgedMulti { seed: usize, octaves: usize, frequency: f32, lacunarity: f32,
+                  persistence: f32, attenuation: f32 },
+}
+
+impl Noise {
+    fn module(&self) -> Box<NoiseModule<[f32; 3], Output=f32> + Sync> {
+        match *self {
+            Noise::Constant { value } => Box::new(Constant::new(value)),
+            Noise::Fbm { seed, octaves, frequency, lacunarity, persistence } =>
+                Box::new(Fbm::new()
+                    .set_seed(seed)
+                    .set_octaves(octaves)
+                    .set_frequency(frequency)
+                    .set_lacunarity(lacunarity)
+                    .set_persistence(persistence)),
+            Noise::Billow { seed, octaves, frequency, lacunarity, persistence } =>
+                Box::new(Billow::new()
+                    .set_seed(seed)
+                    .set_octaves(octaves)
+                    .set_frequency(frequency)
+                    .set_lacunarity(lacunarity)
+                    .set_persistence(persistence)),
+            Noise::RidgedMulti { seed, octaves, frequency, lacunarity, persistence,
+                                 attenuation } =>
+                Box::new(RidgedMulti::new()
+                    .set_seed(seed)
+                    .set_octaves(octaves)
+                    .set_frequency(frequency)
+                    .set_lacunarity(lacunarity)
+                    .set_persistence(persistence)
+                    .set_attenuation(attenuation)),
+        }
+    }
+}
+
+/// Everything needed to generate a planet texture again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recipe {
+    pub seed: u32,
+    /// Red, green and blue noises.
+    pub channels: [Noise; 3],
+}
+
+impl Recipe {
+    pub fn from_seed(seed: u32) -> Recipe {
+        let mut rng = XorShiftRng::from_seed([seed, 0x243F6A88, 0x85A308D3, 0x13198A2E]);
 
-    let mut choices = [0, 0, 1, 1, 2, 2, 3, 3];
-    rng.shuffle(&mut choices);
+        let mut choices = [0, 0, 1, 1, 2, 2, 3, 3];
+        rng.shuffle(&mut choices);
 
-    let mut gen_noise = |i: usize| {
-        match choices[i] {
-            0 => Box::new(Constant::new(rng.gen_range(0.05f32, 0.95)))
-                    as Box<NoiseModule<[f32; 3], Output=f32> + Sync>,
-            1 => Box::new(Fbm::new()
-                    .set_seed(rng.gen())
-                    .set_octaves(rng.gen_range(0, 4))
-                    .set_frequency(rng.gen_range(0.5, 2.0))
-                    .set_lacunarity(rng.gen_range(1.5, 2.5))
-                    .set_persistence(rng.gen_range(0.2, 1.0))),
-            2 => Box::new(Billow::new()
-                    .set_seed(rng.gen())
-                    .set_octaves(rng.gen_range(0, 4))
-                    .set_frequency(rng.gen_range(0.5, 2.0))
-                    .set_lacunarity(rng.gen_range(1.5, 2.5))
-                    .set_persistence(rng.gen_range(0.2, 1.0))),
-            _ => Box::new(RidgedMulti::new()
-                    .set_seed(rng.gen())
-                    .set_octaves(rng.gen_range(0, 4))
-                    .set_frequency(rng.gen_range(0.5, 2.0))
-                    .set_lacunarity(rng.gen_range(1.5, 2.5))
-                    .set_persistence(rng.gen_range(0.5, 1.0))
-                    .set_attenuation(rng.gen_range(1.7, 2.3))),
+        let mut gen_noise = |i: usize| {
+            match choices[i] {
+                0 => Noise::Constant { value: rng.gen_range(0.05f32, 0.95) },
+                1 => Noise::Fbm {
+                    seed: rng.gen(),
+                    octaves: rng.gen_range(0, 4),
+                    frequency: rng.gen_range(0.5, 2.0),
+                    lacunarity: rng.gen_range(1.5, 2.5),
+                    persistence: rng.gen_range(0.2, 1.0),
+                },
+                2 => Noise::Billow {
+                    seed: rng.gen(),
+                    octaves: rng.gen_range(0, 4),
+                    frequency: rng.gen_range(0.5, 2.0),
+                    lacunarity: rng.gen_range(1.5, 2.5),
+                    persistence: rng.gen_range(0.2, 1.0),
+                },
+                _ => Noise::RidgedMulti {
+                    seed: rng.gen(),
+                    octaves: rng.gen_range(0, 4),
+                    frequency: rng.gen_range(0.5, 2.0),
+                    lacunarity: rng.gen_range(1.5, 2.5),
+                    persistence: rng.gen_range(0.5, 1.0),
+                    attenuation: rng.gen_range(1.7, 2.3),
+                },
+            }
+        };
+
+        Recipe {
+            seed: seed,
+            channels: [gen_noise(0), gen_noise(1), gen_noise(2)],
         }
-    };
+    }
+
+    pub fn fill(&self, output: &mut [[u8; 4]], size: usize) {
+        self.fill_region(output, size, 0, 0, 2 * size, size);
+    }
+
+    /// Fills `output` with the `width` by `height` texels at `(x, y)`
+    /// of the `2 * size` by `size` texture, so that textures larger than
+    /// memory can be generated piece by piece.
+    pub fn fill_region(&self,
+                       output: &mut [[u8; 4]],
+                       size: usize,
+                       x: usize,
+                       y: usize,
+                       width: usize,
+                       height: usize) {
+        fill(&*self.channels[0].module(),
+             &*self.channels[1].module(),
+             &*self.channels[2].module(),
+             output, size, (x, y, width, height));
+    }
+}
 
-    fill(&*gen_noise(0), &*gen_noise(1), &*gen_noise(2), output, size);
+impl fmt::Display for Recipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "seed {}: r = {:?}, g = {:?}, b = {:?}",
+               self.seed, self.channels[0], self.channels[1], self.channels[2])
+    }
 }
 
-fn fill<R, G, B>(r: &R, g: &G, b: &B, output: &mut [[u8; 4]], size: usize)
+/// Generates the texture of `seed` into `output`, of `2 * size` by `size` texels.
+pub fn generate(output: &mut [[u8; 4]], size: usize, seed: u32) -> Recipe {
+    let recipe = Recipe::from_seed(seed);
+    recipe.fill(output, size);
+    recipe
+}
+
+fn fill<R, G, B>(r: &R, g: &G, b: &B,
+                 output: &mut [[u8; 4]], size: usize,
+                 region: (usize, usize, usize, usize))
     where R: NoiseModule<[f32; 3], Output=f32> + ?Sized + Sync,
           G: NoiseModule<[f32; 3], Output=f32> + ?Sized + Sync,
           B: NoiseModule<[f32; 3], Output=f32> + ?Sized + Sync
 {
     use rayon::prelude::*;
 
-    debug_assert!(output.len() == 2 * size * size);
-    output.par_chunks_mut(2 * size).enumerate().for_each(|(y, line)| {
+    let (x0, y0, width, height) = region;
+    debug_assert!(output.len() == width * height);
+    debug_assert!(x0 + width <= 2 * size && y0 + height <= size);
+    output.par_chunks_mut(width).enumerate().for_each(|(y, line)| {
+        let y = y0 + y;
         for (x, texel) in line.iter_mut().enumerate() {
+            let x = x0 + x;
             let theta = (x as f32 / size as f32) * f32::consts::PI;
             let phi = -(y as f32 / size as f32) * f32::consts::PI;
             let p = [phi.sin() * theta.cos(),
                      phi.sin() * theta.sin(),
                      phi.cos()];
-            *texel = [noise_to_u8(r.get(p)),
-                      noise_to_u8(g.get(p)),
-                      noise_to_u8(b.get(p)),
-                      0xFF];
+            let (r, g, b) = (noise_to_u8(r.get(p)),
+                             noise_to_u8(g.get(p)),
+                             noise_to_u8(b.get(p)));
+            // alpha masks oceans, where blue dominates
+            let ocean = if b > r && b > g { 0xFF } else { 0x00 };
+            *texel = [r, g, b, ocean];
         }
     });
 }
 
 fn noise_to_u8(v: f32) -> u8 {
     ((v + 1.0) * 255. * 0.5) as u8
-}
\ No newline at end of file
+}
@@ -0,0 +1,473 @@
+//! Virtual texturing of the planets closest to the camera.
+//!
+//! Each planet surface is a mip chain of equirectangular textures cut into
+//! square pages: level `l` is `2^(l+1)` by `2^l` pages, level 0 being
+//! the coarsest. Pages are generated on demand from the planet recipe into
+//! a page cache texture, and an indirection table per planet gives, for each
+//! page of the finest level, the best page in the cache covering it.
+//!
+//! Which pages are needed is estimated on the CPU from the distance of the
+//! planet and the angle under which each page is seen, instead of reading
+//! back what the GPU sampled.
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use gfx;
+use nalgebra::{self, Vector3, UnitQuaternion};
+use scene::Sphere;
+use {Camera, Recipe};
+
+/// Texels along each side of a page.
+pub const PAGE_SIZE: usize = 128;
+/// Pages along each side of the cache texture.
+pub const CACHE_SIDE: usize = 16;
+/// Mip levels of the virtual textures.
+pub const LEVELS: u8 = 6;
+/// Planets with a virtual texture at once.
+pub const NEAR_PLANETS: usize = 4;
+/// Pages generated each frame at most, to spread the cost over frames.
+pub const PAGES_PER_FRAME: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PageId {
+    pub sphere: usize,
+    pub level: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl PageId {
+    /// Page of the same sphere covering this one at `level`.
+    pub fn ancestor(&self, level: u8) -> PageId {
+        debug_assert!(level <= self.level);
+        let shift = self.level - level;
+        PageId { sphere: self.sphere, level: level, x: self.x >> shift, y: self.y >> shift }
+    }
+}
+
+/// Pages across and down at `level`.
+pub fn level_pages(level: u8) -> (u32, u32) {
+    (2 << level, 1 << level)
+}
+
+/// Object space direction of the center of a page, following
+/// the texture coordinates of `generate_icosphere`.
+fn page_direction(page: &PageId) -> Vector3<f32> {
+    let (w, h) = level_pages(page.level);
+    let u = (page.x as f32 + 0.5) / w as f32;
+    let v = (page.y as f32 + 0.5) / h as f32;
+    let latitude = (v - 0.5) * PI;
+    let longitude = -2. * PI * u;
+    Vector3::new(latitude.cos() * longitude.sin(),
+                 latitude.sin(),
+                 latitude.cos() * longitude.cos())
+}
+
+/// Least recently used cache of pages, in the slots of the cache texture.
+#[derive(Debug, Clone)]
+pub struct PageCache {
+    pages: Vec<Option<PageId>>,
+    last_used: Vec<u64>,
+    lookup: HashMap<PageId, usize>,
+    frame: u64,
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> Self {
+        PageCache {
+            pages: vec![None; capacity],
+            last_used: vec![0; capacity],
+            lookup: HashMap::new(),
+            frame: 1,
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn slot(&self, page: &PageId) -> Option<usize> {
+        self.lookup.get(page).cloned()
+    }
+
+    /// Keeps `page` from being evicted this frame, if it is resident.
+    pub fn touch(&mut self, page: &PageId) -> bool {
+        match self.lookup.get(page) {
+            Some(&slot) => {
+                self.last_used[slot] = self.frame;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Slot where `page` must be generated, unless every slot is used this frame.
+    pub fn insert(&mut self, page: PageId) -> Option<usize> {
+        let frame = self.frame;
+        let victim = (0..self.pages.len())
+            .filter(|&s| self.last_used[s] < frame)
+            .min_by_key(|&s| (self.pages[s].is_some(), self.last_used[s]));
+
+        victim.map(|s| {
+            if let Some(evicted) = self.pages[s] {
+                self.lookup.remove(&evicted);
+            }
+            self.pages[s] = Some(page);
+            self.last_used[s] = frame;
+            self.lookup.insert(page, s);
+            s
+        })
+    }
+
+    pub fn clear(&mut self) {
+        for page in &mut self.pages {
+            *page = None;
+        }
+        for last_used in &mut self.last_used {
+            *last_used = 0;
+        }
+        self.lookup.clear();
+    }
+}
+
+/// Pages of `sphere` needed to draw it `projected_radius` pixels wide.
+///
+/// Pages facing the camera get the level matching the screen resolution,
+/// pages seen at grazing angles coarser ones and hidden pages none,
+/// except for level 0 which always covers the whole sphere.
+pub fn request_pages(sphere: usize,
+                     bounds: &Sphere,
+                     rotation: &UnitQuaternion<f32>,
+                     eye: &nalgebra::Point3<f32>,
+                     projected_radius: f32,
+                     requests: &mut Vec<PageId>) {
+    // half a circumference is seen across the diameter,
+    // which the equator of level l covers with 2^l pages
+    let needed = (PI * projected_radius / PAGE_SIZE as f32).log2().ceil();
+    let finest = if needed > 0. { cmp::min(needed as u8, LEVELS - 1) } else { 0 };
+
+    let mut requested = HashSet::new();
+    let (w, h) = level_pages(finest);
+    for y in 0..h {
+        for x in 0..w {
+            let page = PageId { sphere: sphere, level: finest, x: x, y: y };
+            let normal = rotation * page_direction(&page);
+            let to_eye = *eye - (bounds.center + normal * bounds.radius);
+            let facing = normal.dot(&to_eye) / to_eye.norm();
+
+            let coarser = if facing <= 0. {
+                continue;
+            } else if facing < 0.3 {
+                2
+            } else if facing < 0.6 {
+                1
+            } else {
+                0
+            };
+            let level = finest.saturating_sub(coarser);
+            requested.insert(page.ancestor(level));
+        }
+    }
+
+    let (w, h) = level_pages(0);
+    for y in 0..h {
+        for x in 0..w {
+            requested.insert(PageId { sphere: sphere, level: 0, x: x, y: y });
+        }
+    }
+
+    requests.extend(requested);
+}
+
+/// Page cache texture and indirection tables of the nearest planets.
+pub struct VirtualTexture<R: gfx::Resources> {
+    /// Off by default, so that it does not weigh on measured frames.
+    pub enabled: bool,
+    cache: PageCache,
+    /// Spheres with an indirection table, by table index.
+    near: Vec<usize>,
+    tables: Vec<Vec<[u8; 4]>>,
+    requests: Vec<PageId>,
+    page: Vec<[u8; 4]>,
+    /// Pages generated by the last `update`.
+    pub generated: usize,
+    pub cache_texture: gfx::handle::Texture<R, gfx::format::R8_G8_B8_A8>,
+    pub cache_view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    pub table_texture: gfx::handle::Texture<R, gfx::format::R8_G8_B8_A8>,
+    pub table_view: gfx::handle::ShaderResourceView<R, [f32; 4]>,
+}
+
+impl<R: gfx::Resources> VirtualTexture<R> {
+    pub fn new<F: gfx::Factory<R>>(factory: &mut F) -> Self {
+        let side = (CACHE_SIDE * PAGE_SIZE) as gfx::texture::Size;
+        let cache_texture = factory.create_texture(
+            gfx::texture::Kind::D2(side, side, gfx::texture::AaMode::Single),
+            1,
+            gfx::memory::SHADER_RESOURCE,
+            gfx::memory::Usage::Dynamic,
+            Some(gfx::format::ChannelType::Unorm)
+        ).expect("could not create page cache texture");
+        let cache_view = factory
+            .view_texture_as_shader_resource::<gfx::format::Rgba8>(&cache_texture, (0, 0), gfx::format::Swizzle::new())
+            .expect("could not create page cache view");
+
+        let (w, h) = level_pages(LEVELS - 1);
+        let table_texture = factory.create_texture(
+            gfx::texture::Kind::D2Array(w as gfx::texture::Size,
+                                        h as gfx::texture::Size,
+                                        NEAR_PLANETS as gfx::texture::Size,
+                                        gfx::texture::AaMode::Single),
+            1,
+            gfx::memory::SHADER_RESOURCE,
+            gfx::memory::Usage::Dynamic,
+            Some(gfx::format::ChannelType::Unorm)
+        ).expect("could not create page table texture");
+        let table_view = factory
+            .view_texture_as_shader_resource::<gfx::format::Rgba8>(&table_texture, (0, 0), gfx::format::Swizzle::new())
+            .expect("could not create page table view");
+
+        VirtualTexture {
+            enabled: false,
+            cache: PageCache::new(CACHE_SIDE * CACHE_SIDE),
+            near: Vec::with_capacity(NEAR_PLANETS),
+            tables: vec![vec![[0; 4]; (w * h) as usize]; NEAR_PLANETS],
+            requests: Vec::new(),
+            page: vec![[0; 4]; PAGE_SIZE * PAGE_SIZE],
+            generated: 0,
+            cache_texture: cache_texture,
+            cache_view: cache_view,
+            table_texture: table_texture,
+            table_view: table_view,
+        }
+    }
+
+    /// `(finest pages across, finest pages down, cache pages across, cache pages down)`.
+    pub fn gpu_params(&self) -> [f32; 4] {
+        let (w, h) = level_pages(LEVELS - 1);
+        [w as f32, h as f32, CACHE_SIDE as f32, CACHE_SIDE as f32]
+    }
+
+    /// Indirection table of `sphere`, if it has one.
+    pub fn table(&self, sphere: usize) -> Option<usize> {
+        self.near.iter().position(|&s| s == sphere)
+    }
+
+    /// Forgets every page, since the seeds changed.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Picks the nearest planets, generates the pages they miss the most
+    /// and uploads their indirection tables.
+    /// Returns whether the planets with a table changed.
+    pub fn update<C>(&mut self,
+                     spheres: &[Sphere],
+                     rotations: &[UnitQuaternion<f32>],
+                     seeds: &[u32],
+                     camera: &Camera,
+                     viewport_height: f32,
+                     encoder: &mut gfx::Encoder<R, C>) -> bool
+        where C: gfx::CommandBuffer<R>
+    {
+        let eye = camera.position();
+
+        // planets seen larger than their regular texture, nearest first
+        let mut near: Vec<(f32, usize)> = spheres.iter().enumerate()
+            .map(|(i, s)| (camera.projected_radius(&s.center, s.radius, viewport_height), i))
+            .filter(|&(r, _)| self.enabled && PI * r > PAGE_SIZE as f32)
+            .collect();
+        near.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(cmp::Ordering::Equal));
+        near.truncate(NEAR_PLANETS);
+        let near: Vec<usize> = near.into_iter().map(|(_, i)| i).collect();
+        let changed = near != self.near;
+        self.near = near;
+
+        self.cache.next_frame();
+        self.requests.clear();
+        for &i in &self.near {
+            let radius = camera.projected_radius(&spheres[i].center, spheres[i].radius,
+                                                 viewport_height);
+            request_pages(i, &spheres[i], &rotations[i], &eye, radius, &mut self.requests);
+        }
+
+        // coarse pages first, so that every planet is covered early
+        self.requests.sort_by_key(|page| page.level);
+        self.generated = 0;
+        for r in 0..self.requests.len() {
+            let page = self.requests[r];
+            if self.cache.touch(&page) || self.generated == PAGES_PER_FRAME {
+                continue;
+            }
+            let slot = match self.cache.insert(page) {
+                Some(slot) => slot,
+                None => break,
+            };
+
+            let size = PAGE_SIZE << page.level;
+            Recipe::from_seed(seeds[page.sphere]).fill_region(
+                &mut self.page, size,
+                page.x as usize * PAGE_SIZE, page.y as usize * PAGE_SIZE,
+                PAGE_SIZE, PAGE_SIZE);
+            let info = gfx::texture::ImageInfoCommon {
+                xoffset: ((slot % CACHE_SIDE) * PAGE_SIZE) as gfx::texture::Size,
+                yoffset: ((slot / CACHE_SIDE) * PAGE_SIZE) as gfx::texture::Size,
+                zoffset: 0,
+                width: PAGE_SIZE as gfx::texture::Size,
+                height: PAGE_SIZE as gfx::texture::Size,
+                depth: 1,
+                format: (),
+                mipmap: 0
+            };
+            encoder.update_texture::<_, gfx::format::Rgba8>
+                (&self.cache_texture, None, info, &self.page[..])
+                .unwrap();
+            self.generated += 1;
+        }
+
+        let (w, h) = level_pages(LEVELS - 1);
+        for (t, &sphere) in self.near.iter().enumerate() {
+            let cache = &self.cache;
+            for (e, entry) in self.tables[t].iter_mut().enumerate() {
+                let finest = PageId {
+                    sphere: sphere,
+                    level: LEVELS - 1,
+                    x: e as u32 % w,
+                    y: e as u32 / w,
+                };
+                // the finest resident page covering this one
+                *entry = (0..LEVELS).rev()
+                    .map(|level| finest.ancestor(level))
+                    .filter_map(|page| cache.slot(&page).map(|slot| (page, slot)))
+                    .next()
+                    .map(|(page, slot)| [(slot % CACHE_SIDE) as u8,
+                                         (slot / CACHE_SIDE) as u8,
+                                         page.level,
+                                         0xFF])
+                    .unwrap_or([0; 4]);
+            }
+
+            let info = gfx::texture::ImageInfoCommon {
+                xoffset: 0,
+                yoffset: 0,
+                zoffset: t as gfx::texture::Size,
+                width: w as gfx::texture::Size,
+                height: h as gfx::texture::Size,
+                depth: 1,
+                format: (),
+                mipmap: 0
+            };
+            encoder.update_texture::<_, gfx::format::Rgba8>
+                (&self.table_texture, None, info, &self.tables[t][..])
+                .unwrap();
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, UnitQuaternion};
+    use scene::Sphere;
+    use super::*;
+
+    fn page(sphere: usize, level: u8, x: u32, y: u32) -> PageId {
+        PageId { sphere: sphere, level: level, x: x, y: y }
+    }
+
+    #[test]
+    fn ancestors() {
+        let p = page(3, 4, 13, 6);
+        assert_eq!(p.ancestor(4), p);
+        assert_eq!(p.ancestor(2), page(3, 2, 3, 1));
+        assert_eq!(p.ancestor(0), page(3, 0, 0, 0));
+        assert_eq!(level_pages(0), (2, 1));
+        assert_eq!(level_pages(3), (16, 8));
+    }
+
+    #[test]
+    fn cache_fills_empty_slots_first() {
+        let mut cache = PageCache::new(3);
+        assert_eq!(cache.insert(page(0, 0, 0, 0)), Some(0));
+        assert_eq!(cache.insert(page(0, 0, 1, 0)), Some(1));
+        assert_eq!(cache.slot(&page(0, 0, 1, 0)), Some(1));
+        assert_eq!(cache.slot(&page(1, 0, 1, 0)), None);
+
+        cache.next_frame();
+        assert_eq!(cache.insert(page(1, 0, 0, 0)), Some(2));
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = PageCache::new(2);
+        let (a, b, c) = (page(0, 0, 0, 0), page(0, 0, 1, 0), page(0, 1, 0, 0));
+        cache.insert(a);
+        cache.insert(b);
+
+        cache.next_frame();
+        assert!(cache.touch(&a));
+        assert!(!cache.touch(&c));
+        cache.next_frame();
+        // b was last used a frame before a
+        assert_eq!(cache.insert(c), Some(1));
+        assert_eq!(cache.slot(&b), None);
+        assert_eq!(cache.slot(&a), Some(0));
+        assert_eq!(cache.slot(&c), Some(1));
+    }
+
+    #[test]
+    fn cache_full_this_frame() {
+        let mut cache = PageCache::new(2);
+        cache.insert(page(0, 0, 0, 0));
+        cache.insert(page(0, 0, 1, 0));
+        assert_eq!(cache.insert(page(0, 1, 0, 0)), None);
+
+        cache.next_frame();
+        cache.touch(&page(0, 0, 1, 0));
+        assert_eq!(cache.insert(page(0, 1, 0, 0)), Some(0));
+        assert_eq!(cache.insert(page(0, 1, 1, 0)), None);
+
+        cache.clear();
+        assert_eq!(cache.slot(&page(0, 1, 0, 0)), None);
+        assert_eq!(cache.insert(page(0, 1, 1, 0)), Some(0));
+    }
+
+    fn requests(projected_radius: f32) -> Vec<PageId> {
+        let sphere = Sphere { center: Point3::new(0., 0., 0.), radius: 1. };
+        let eye = Point3::new(0., 0., 5.);
+        let mut requests = Vec::new();
+        request_pages(7, &sphere, &UnitQuaternion::identity(), &eye, projected_radius,
+                      &mut requests);
+        requests
+    }
+
+    #[test]
+    fn far_spheres_need_the_coarsest_level() {
+        let mut pages = requests(10.);
+        pages.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(pages, vec![page(7, 0, 0, 0), page(7, 0, 1, 0)]);
+    }
+
+    #[test]
+    fn near_spheres_need_the_pages_they_face() {
+        let pages = requests(2000.);
+        let finest = LEVELS - 1;
+        let (w, h) = level_pages(finest);
+
+        assert!(pages.iter().all(|p| p.sphere == 7 && p.level <= finest));
+        let mut unique = pages.clone();
+        unique.sort_by_key(|p| (p.level, p.x, p.y));
+        unique.dedup();
+        assert_eq!(unique.len(), pages.len());
+
+        // level 0 always covers the whole sphere
+        assert!(pages.contains(&page(7, 0, 0, 0)));
+        assert!(pages.contains(&page(7, 0, 1, 0)));
+        // u = 0 faces +z, u = 0.5 faces away
+        assert!(pages.contains(&page(7, finest, 0, h / 2)));
+        assert!(!pages.contains(&page(7, finest, w / 2, h / 2)));
+        // far fewer than every finest page
+        assert!(pages.len() < (w * h / 2) as usize);
+    }
+}